@@ -0,0 +1,324 @@
+//! Brushes to edit the terrain and its fields for setting up experiments.
+//! In brush mode (toggled with Tab) holding the right mouse button applies the selected brush
+//! at the cursor. The strength is weighted with the quadratic profile of [`Field::add_kernel`].
+//!
+//! [`Field::add_kernel`]: crate::domain::Field::add_kernel
+
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::input::{egui_wants_any_keyboard_input, egui_wants_any_pointer_input};
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+
+use crate::domain;
+use crate::organism::{self, Organism};
+use crate::parameters;
+use crate::player_inputs::{TerrainRayCast, ToolMode};
+use crate::replay::{Intervention, Replay};
+use crate::terrain::{Surface, Terrain};
+use crate::undo;
+
+#[derive(Default, PartialEq, Copy, Clone, Debug)]
+pub enum BrushKind {
+    #[default]
+    Raise,
+    Lower,
+    Smooth,
+    Moisture,
+    Dry,
+    Nutrients,
+    Plant,
+    Clear,
+}
+
+impl BrushKind {
+    pub const ALL: [BrushKind; 8] = [
+        BrushKind::Raise,
+        BrushKind::Lower,
+        BrushKind::Smooth,
+        BrushKind::Moisture,
+        BrushKind::Dry,
+        BrushKind::Nutrients,
+        BrushKind::Plant,
+        BrushKind::Clear,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BrushKind::Raise => "raise terrain",
+            BrushKind::Lower => "lower terrain",
+            BrushKind::Smooth => "smooth terrain",
+            BrushKind::Moisture => "add moisture",
+            BrushKind::Dry => "remove moisture",
+            BrushKind::Nutrients => "add nutrients",
+            BrushKind::Plant => "plant organisms",
+            BrushKind::Clear => "clear organisms",
+        }
+    }
+
+    // Field modified by a continuous brush.
+    pub fn field(self) -> Option<undo::EditableField> {
+        match self {
+            BrushKind::Raise | BrushKind::Lower | BrushKind::Smooth => {
+                Some(undo::EditableField::Height)
+            }
+            BrushKind::Moisture | BrushKind::Dry => Some(undo::EditableField::Moisture),
+            BrushKind::Nutrients => Some(undo::EditableField::Nutrients),
+            BrushKind::Plant | BrushKind::Clear => None,
+        }
+    }
+
+    // Applied once per click instead of continuously.
+    pub fn is_discrete(self) -> bool {
+        matches!(self, BrushKind::Plant | BrushKind::Clear)
+    }
+}
+
+#[derive(Resource)]
+pub struct BrushSettings {
+    pub kind: BrushKind,
+    pub radius: f32,
+    // change per second at the center of the brush
+    pub strength: f32,
+    // organisms per unit area planted with one click
+    pub plant_density: f32,
+}
+
+impl Default for BrushSettings {
+    fn default() -> Self {
+        BrushSettings {
+            kind: BrushKind::default(),
+            radius: 2.0,
+            strength: 1.0,
+            plant_density: 2.0,
+        }
+    }
+}
+
+const BRUSH_COLOR: Color = Color::srgb(0.2, 0.6, 1.0);
+
+pub struct BrushPlugin;
+
+impl Plugin for BrushPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BrushSettings>()
+            .add_systems(
+                Update,
+                (field_brush_system, organism_brush_system)
+                    .run_if(resource_equals(ToolMode::Brush))
+                    .run_if(not(resource_exists::<Replay>))
+                    .run_if(
+                        not(egui_wants_any_keyboard_input).and(not(egui_wants_any_pointer_input)),
+                    ),
+            )
+            .add_systems(EguiPrimaryContextPass, brush_ui_system);
+    }
+}
+
+// Continuous brushes which modify the height map or a field of the surface.
+pub fn field_brush_system(
+    mut terrain_ray_cast: TerrainRayCast,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    settings: Res<BrushSettings>,
+    mut field_query: Query<(&mut Terrain, &mut Surface)>,
+    mut organism_query: Query<&mut Transform, With<Organism>>,
+    general_params: Res<parameters::GeneralParameters>,
+    // brushes also work while the simulation is paused
+    time: Res<Time<Real>>,
+    mut gizmos: Gizmos,
+    mut undo_history: ResMut<undo::UndoHistory>,
+    mut interventions: MessageWriter<Intervention>,
+    mut stroke: Local<Option<undo::FieldSnapshot>>,
+) {
+    let (mut terrain, mut surface) = field_query.single_mut().unwrap();
+    let is_pressed = mouse_button_input.pressed(MouseButton::Right);
+
+    // a stroke lasts until the button is released and is undone as a whole
+    if !is_pressed
+        && let Some(snapshot) = stroke.take()
+        && let Some(action) = snapshot.into_action()
+    {
+        undo_history.record(action);
+    }
+
+    let Some(hit_point) = terrain_ray_cast.cursor_hit() else {
+        return;
+    };
+
+    // outline of the brush
+    gizmos.circle(
+        Isometry3d::new(
+            hit_point + Vec3::Y * 0.05,
+            Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+        ),
+        settings.radius,
+        BRUSH_COLOR,
+    );
+
+    let Some(field) = settings.kind.field() else {
+        return;
+    };
+    if !is_pressed {
+        return;
+    }
+    if stroke.is_none() {
+        interventions.write(Intervention::EditField {
+            field: field.name().to_string(),
+            position: hit_point.xz().to_array(),
+        });
+    }
+    let snapshot =
+        stroke.get_or_insert_with(|| undo::FieldSnapshot::new(field, &terrain, &surface));
+    if let Some(region) = field
+        .get(&terrain, &surface)
+        .kernel_region(hit_point.xz(), settings.radius)
+    {
+        snapshot.add_region(region);
+    }
+
+    let center = hit_point.xz();
+    let radius = settings.radius;
+    let amount = settings.strength * time.delta_secs();
+    match settings.kind {
+        BrushKind::Raise => terrain.height_map.add_kernel(center, radius, amount),
+        BrushKind::Lower => terrain.height_map.add_kernel(center, radius, -amount),
+        BrushKind::Smooth => terrain.height_map.smooth_kernel(center, radius, amount),
+        BrushKind::Moisture | BrushKind::Dry => {
+            let sign = if settings.kind == BrushKind::Dry {
+                -1.0
+            } else {
+                1.0
+            };
+            surface
+                .moisture
+                .apply_kernel(center, radius, |moisture, weight| {
+                    *moisture = (*moisture + sign * amount * weight).clamp(0.0, 1.0);
+                });
+        }
+        BrushKind::Nutrients => surface.nutrients.add_kernel(center, radius, amount),
+        BrushKind::Plant | BrushKind::Clear => {}
+    }
+
+    // keep organisms on the modified surface
+    if matches!(
+        settings.kind,
+        BrushKind::Raise | BrushKind::Lower | BrushKind::Smooth
+    ) {
+        for mut transform in organism_query.iter_mut() {
+            let p = transform.translation.xz();
+            if p.distance(center) < radius {
+                transform.translation.y =
+                    terrain.height_map.get_bilinear(p) - general_params.grass.below_surface_depth;
+            }
+        }
+    }
+}
+
+// Brushes which plant or remove organisms in a disc with each click.
+pub fn organism_brush_system(
+    mut commands: Commands,
+    mut terrain_ray_cast: TerrainRayCast,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    settings: Res<BrushSettings>,
+    mut field_query: Query<(&Terrain, &mut Surface)>,
+    organism_query: Query<(Entity, &Transform, &Organism)>,
+    grass_assets: Res<crate::GrassAssets>,
+    general_params: Res<parameters::GeneralParameters>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+    mut undo_history: ResMut<undo::UndoHistory>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+) {
+    if !settings.kind.is_discrete() || !mouse_button_input.just_released(MouseButton::Right) {
+        return;
+    }
+    let Some(hit_point) = terrain_ray_cast.cursor_hit() else {
+        return;
+    };
+
+    let (terrain, mut surface) = field_query.single_mut().unwrap();
+    let center = hit_point.xz();
+    let area = Circle::new(settings.radius);
+    match settings.kind {
+        BrushKind::Plant => {
+            let count = (settings.plant_density * area.area()).round() as usize;
+            let mut spawned = Vec::with_capacity(count);
+            for _ in 0..count {
+                let p = area.sample_interior(&mut rng) + center;
+                if !domain::bounds().contains(p) {
+                    continue;
+                }
+                let (_, id) = organism::spawn_seedling(
+                    &mut commands,
+                    &mut organism_ids,
+                    &grass_assets,
+                    terrain,
+                    p,
+                    None,
+                    &general_params.grass,
+                    &mut rng,
+                );
+                spawned.push(id);
+            }
+            commands.write_message(Intervention::Plant {
+                position: center.to_array(),
+                count: spawned.len(),
+            });
+            undo_history.record_spawned(spawned);
+        }
+        BrushKind::Clear => {
+            let mut removed = Vec::new();
+            for (id, transform, organism) in organism_query.iter() {
+                if transform.translation.xz().distance(center) < settings.radius {
+                    organism::remove_organism(
+                        &mut commands,
+                        &mut surface,
+                        id,
+                        transform,
+                        organism,
+                        organism::DeathCause::Removed,
+                    );
+                    removed.push((*transform, organism.clone()));
+                }
+            }
+            commands.write_message(Intervention::Remove {
+                position: center.to_array(),
+                count: removed.len(),
+            });
+            undo_history.record_removed(removed);
+        }
+        _ => {}
+    }
+}
+
+pub fn brush_ui_system(
+    mut contexts: EguiContexts,
+    tool_mode: Res<ToolMode>,
+    mut settings: ResMut<BrushSettings>,
+) -> Result {
+    if *tool_mode != ToolMode::Brush {
+        return Ok(());
+    }
+
+    let settings = &mut *settings;
+    egui::Window::new("Brush")
+        .resizable(false)
+        .anchor(Align2::RIGHT_CENTER, egui::vec2(-5.0, 0.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            egui::ComboBox::from_label("brush")
+                .selected_text(settings.kind.name())
+                .show_ui(ui, |ui| {
+                    for kind in BrushKind::ALL {
+                        ui.selectable_value(&mut settings.kind, kind, kind.name());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut settings.radius, 0.1..=16.0).text("radius"));
+            if settings.kind == BrushKind::Plant {
+                ui.add(egui::Slider::new(&mut settings.plant_density, 0.1..=20.0).text("density"));
+            } else if !settings.kind.is_discrete() {
+                ui.add(egui::Slider::new(&mut settings.strength, 0.01..=10.0).text("strength"));
+            }
+        });
+
+    Ok(())
+}
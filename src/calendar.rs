@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+use std::f32::consts::PI;
+use std::fmt;
+
+use crate::parameters;
+
+/// Axial tilt of the earth [rad]
+const AXIAL_TILT: f32 = 23.44 * PI / 180.0;
+
+#[derive(Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct CalendarParameters {
+    pub days_per_year: u32,
+    // How strongly the day length modulates growth and propagation rates.
+    // 0 disables the seasonal modulation.
+    pub seasonality: f32,
+}
+
+impl Default for CalendarParameters {
+    fn default() -> Self {
+        CalendarParameters {
+            days_per_year: 24,
+            seasonality: 1.0,
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Copy, Clone, Debug)]
+pub enum Season {
+    #[default]
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+impl fmt::Display for Season {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Season::Spring => "spring",
+            Season::Summer => "summer",
+            Season::Autumn => "autumn",
+            Season::Winter => "winter",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Simulation date. The year starts with the spring equinox.
+#[derive(Resource, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Calendar {
+    elapsed_days: f64,
+    days_per_year: u32,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        // start at noon so the scene is lit
+        Calendar {
+            elapsed_days: 0.5,
+            days_per_year: CalendarParameters::default().days_per_year,
+        }
+    }
+}
+
+impl Calendar {
+    pub fn advance(&mut self, dt: f32, day_duration: f32, days_per_year: u32) {
+        self.elapsed_days += (dt / day_duration) as f64;
+        self.days_per_year = days_per_year.max(1);
+    }
+
+    // total number of completed days
+    pub fn day(&self) -> u32 {
+        self.elapsed_days as u32
+    }
+
+    // zero based day in the current year
+    pub fn day_of_year(&self) -> u32 {
+        self.day() % self.days_per_year
+    }
+
+    // zero based year
+    pub fn year(&self) -> u32 {
+        self.day() / self.days_per_year
+    }
+
+    // fraction of the current day in [0,1), 0.5 is noon
+    pub fn time_of_day(&self) -> f32 {
+        self.elapsed_days.fract() as f32
+    }
+
+    // fraction of the current year in [0,1)
+    pub fn year_fraction(&self) -> f32 {
+        (self.elapsed_days / self.days_per_year as f64).fract() as f32
+    }
+
+    pub fn season(&self) -> Season {
+        match (self.year_fraction() * 4.0) as u32 {
+            0 => Season::Spring,
+            1 => Season::Summer,
+            2 => Season::Autumn,
+            _ => Season::Winter,
+        }
+    }
+
+    // zero based day in the current season
+    pub fn day_of_season(&self) -> u32 {
+        let season_length = self.days_per_year as f32 / 4.0;
+        (self.day_of_year() as f32 % season_length) as u32
+    }
+
+    pub fn solar_declination(&self) -> f32 {
+        AXIAL_TILT * (2.0 * PI * self.year_fraction()).sin()
+    }
+
+    // Direction towards the sun at the current time.
+    pub fn sun_direction(&self, params: &parameters::SunParameters) -> Vec3 {
+        // without movement the sun stays at its noon position of the current date
+        let time_of_day = if params.is_moving {
+            self.time_of_day()
+        } else {
+            0.5
+        };
+        sun_direction(params.latitude, self.solar_declination(), time_of_day)
+    }
+
+    // Factor for rates of seasonal processes like growth and propagation.
+    // It is 1 on the equinoxes and scales with the relative length of the day otherwise.
+    pub fn growth_factor(&self, latitude: f32, seasonality: f32) -> f32 {
+        let daylight = daylight_fraction(latitude, self.solar_declination());
+        (1.0 + seasonality * (2.0 * daylight - 1.0)).max(0.0)
+    }
+}
+
+// Fraction of the day in which the sun is above the horizon.
+// The latitude is given in degrees, the declination in radians.
+pub fn daylight_fraction(latitude: f32, declination: f32) -> f32 {
+    let cos_hour_angle = -latitude.to_radians().tan() * declination.tan();
+    // clamping covers polar day and night
+    cos_hour_angle.clamp(-1.0, 1.0).acos() / PI
+}
+
+// Direction towards the sun in world space with x pointing east and -z pointing north.
+// The latitude is given in degrees, the declination in radians and the time of day in [0,1).
+pub fn sun_direction(latitude: f32, declination: f32, time_of_day: f32) -> Vec3 {
+    let (sin_lat, cos_lat) = latitude.to_radians().sin_cos();
+    let (sin_dec, cos_dec) = declination.sin_cos();
+    let (sin_hour, cos_hour) = (2.0 * PI * (time_of_day - 0.5)).sin_cos();
+
+    let east = -cos_dec * sin_hour;
+    let north = cos_lat * sin_dec - sin_lat * cos_dec * cos_hour;
+    let up = sin_lat * sin_dec + cos_lat * cos_dec * cos_hour;
+    Vec3::new(east, up, -north)
+}
+
+pub fn advance_calendar_system(
+    mut calendar: ResMut<Calendar>,
+    time: Res<Time>,
+    params: Res<parameters::GeneralParameters>,
+) {
+    calendar.advance(
+        time.delta_secs(),
+        params.sun.day_duration,
+        params.calendar.days_per_year,
+    );
+}
@@ -0,0 +1,335 @@
+//! Command line options for reproducible batch runs, e.g.
+//! `eco-sim --seed 42 --config params.ron --duration 3600 --headless --output runs/42`.
+
+use bevy::prelude::*;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::lineage::LineageStore;
+use crate::metrics::{Metrics, RunSummary};
+use crate::organism::Organism;
+use crate::parameters::GeneralParameters;
+use crate::replay::EventLog;
+use crate::snapshot::SimulationState;
+use crate::spatial::SpatialStatistics;
+use crate::terrain::Surface;
+
+const USAGE: &str = "\
+Usage: eco-sim [OPTIONS]
+
+Options:
+  --seed <N>          seed of the random number generator and the terrain
+  --config <FILE>     parameters in RON format, missing values keep their defaults
+  --world-size <N>    side length of the square domain, a power of two [default: 64]
+  --duration <S>      stop after S simulated seconds and write the results
+  --fixed-hz <HZ>     rate of the fixed simulation step [default: 60]
+  --output <DIR>      directory for metrics and snapshots [default: .]
+  --headless          run without a window as fast as possible
+  --snapshot <FILE>   start from a snapshot saved with F6 or with --save-snapshot
+  --save-snapshot     also write a snapshot at the end of a run
+  --scenario <FILE>   execute the timed events described in FILE
+  --script <FILE>     execute the callbacks of a Rhai script, reloaded when modified
+  --sweep <FILE>      run headless simulations for a parameter sweep described in FILE
+  --threads <N>       number of worker threads of the simulation
+  --remote <PORT>     accept remote control requests on localhost:PORT, e.g. 15702
+  --replay <FILE>     show the population history of an event log saved with F7
+  -h, --help          print this message";
+
+const MIN_WORLD_SIZE: usize = 16;
+const MAX_WORLD_SIZE: usize = 1024;
+
+// file names of the results written at the end of a run
+pub const METRICS_FILE: &str = "metrics.csv";
+pub const SPATIAL_FILE: &str = "spatial.csv";
+pub const PARAMETERS_FILE: &str = "parameters.ron";
+pub const SNAPSHOT_FILE: &str = "snapshot.ron";
+pub const SUMMARY_FILE: &str = "summary.ron";
+pub const EVENTS_FILE: &str = "events.bin";
+pub const LINEAGE_TREE_FILE: &str = "lineage.nwk";
+pub const LINEAGE_FILE: &str = "lineage.json";
+pub const CLONES_FILE: &str = "clones.csv";
+
+#[derive(Resource, Clone, Debug)]
+pub struct Options {
+    // random seed if none is given
+    pub seed: Option<u64>,
+    pub config: Option<PathBuf>,
+    pub world_size: usize,
+    // simulated time after which the application exits [s]
+    pub duration: Option<f64>,
+    pub fixed_hz: f64,
+    pub output_dir: PathBuf,
+    pub is_headless: bool,
+    pub snapshot: Option<PathBuf>,
+    // write a snapshot together with the results
+    pub is_snapshot_saved: bool,
+    pub scenario: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+    pub sweep: Option<PathBuf>,
+    // all available cores if none is given
+    pub threads: Option<usize>,
+    // port of the remote control server, disabled if none is given
+    pub remote_port: Option<u16>,
+    // event log to replay instead of simulating
+    pub replay: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            seed: None,
+            config: None,
+            world_size: 64,
+            duration: None,
+            fixed_hz: 60.0,
+            output_dir: PathBuf::from("."),
+            is_headless: false,
+            snapshot: None,
+            is_snapshot_saved: false,
+            scenario: None,
+            script: None,
+            sweep: None,
+            threads: None,
+            remote_port: None,
+            replay: None,
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(name: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {name}: {value}"))
+}
+
+impl Options {
+    // Parse the arguments without the program name.
+    // Both `--name value` and `--name=value` are accepted.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, mut inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("missing value for {name}"))
+            };
+
+            match name.as_str() {
+                "--seed" => options.seed = Some(parse_value(&name, value()?)?),
+                "--config" => options.config = Some(PathBuf::from(value()?)),
+                "--world-size" => options.world_size = parse_value(&name, value()?)?,
+                "--duration" => options.duration = Some(parse_value(&name, value()?)?),
+                "--fixed-hz" => options.fixed_hz = parse_value(&name, value()?)?,
+                "--output" => options.output_dir = PathBuf::from(value()?),
+                "--headless" => options.is_headless = true,
+                "--snapshot" => options.snapshot = Some(PathBuf::from(value()?)),
+                "--save-snapshot" => options.is_snapshot_saved = true,
+                "--scenario" => options.scenario = Some(PathBuf::from(value()?)),
+                "--script" => options.script = Some(PathBuf::from(value()?)),
+                "--sweep" => options.sweep = Some(PathBuf::from(value()?)),
+                "--threads" => options.threads = Some(parse_value(&name, value()?)?),
+                "--remote" => options.remote_port = Some(parse_value(&name, value()?)?),
+                "--replay" => options.replay = Some(PathBuf::from(value()?)),
+                _ => return Err(format!("unknown option {name}")),
+            }
+        }
+
+        if !options.world_size.is_power_of_two()
+            || !(MIN_WORLD_SIZE..=MAX_WORLD_SIZE).contains(&options.world_size)
+        {
+            return Err(format!(
+                "--world-size has to be a power of two in [{MIN_WORLD_SIZE}, {MAX_WORLD_SIZE}]"
+            ));
+        }
+        if !options.fixed_hz.is_finite() || options.fixed_hz <= 0.0 {
+            return Err("--fixed-hz has to be positive".to_string());
+        }
+        if options
+            .duration
+            .is_some_and(|duration| !duration.is_finite() || duration < 0.0)
+        {
+            return Err("--duration has to be a non-negative number".to_string());
+        }
+        if options.threads == Some(0) {
+            return Err("--threads has to be positive".to_string());
+        }
+        Ok(options)
+    }
+
+    // Options of this process. Prints the usage and exits on --help or invalid arguments.
+    pub fn from_env() -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            println!("{USAGE}");
+            std::process::exit(0);
+        }
+        Options::parse(args).unwrap_or_else(|err| {
+            eprintln!("{err}\n\n{USAGE}");
+            std::process::exit(2);
+        })
+    }
+
+    // The domain has a side length of 2^world_size_pow.
+    pub fn world_size_pow(&self) -> u32 {
+        self.world_size.ilog2()
+    }
+
+    // Path of a file in the output directory, which is created if necessary.
+    pub fn output_path(&self, file_name: &str) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.output_dir)?;
+        Ok(self.output_dir.join(file_name))
+    }
+}
+
+// Parameters from the config file or the defaults.
+pub fn initial_parameters(options: &Options) -> Result<GeneralParameters, String> {
+    let Some(path) = &options.config else {
+        return Ok(GeneralParameters::default());
+    };
+    GeneralParameters::load(path)
+        .map_err(|err| format!("failed to load config {}: {err}", path.display()))
+}
+
+// Ends the run before the requested duration, e.g. from a scenario.
+#[derive(Resource, Default)]
+pub struct StopRequest {
+    pub is_requested: bool,
+}
+
+// Write the results and exit once the requested duration has been simulated or a stop
+// was requested.
+pub fn end_of_run_system(
+    time: Res<Time>,
+    options: Res<Options>,
+    stop: Res<StopRequest>,
+    metrics: Res<Metrics>,
+    state: SimulationState,
+    surface_query: Query<&Surface>,
+    organism_query: Query<&Transform, With<Organism>>,
+    log: Option<Res<EventLog>>,
+    lineage: Res<LineageStore>,
+    mut exit: MessageWriter<AppExit>,
+    mut is_finished: Local<bool>,
+) {
+    let is_due = options
+        .duration
+        .is_some_and(|duration| time.elapsed_secs_f64() >= duration);
+    if *is_finished || !(is_due || stop.is_requested) {
+        return;
+    }
+    *is_finished = true;
+
+    let surface = surface_query.single().unwrap();
+    let positions: Vec<Vec2> = organism_query
+        .iter()
+        .map(|transform| transform.translation.xz())
+        .collect();
+    let spatial_statistics = SpatialStatistics::new(positions.clone(), &surface.veg_density);
+    let summary = RunSummary::new(&metrics, surface, positions);
+    let result = options
+        .output_path(METRICS_FILE)
+        .and_then(|path| metrics.write_csv(&path))
+        .and_then(|_| options.output_path(SPATIAL_FILE))
+        .and_then(|path| spatial_statistics.write_csv(&path))
+        .and_then(|_| options.output_path(PARAMETERS_FILE))
+        .and_then(|path| state.parameters().save(&path))
+        .and_then(|_| {
+            if options.is_snapshot_saved {
+                options
+                    .output_path(SNAPSHOT_FILE)
+                    .and_then(|path| state.snapshot().save(&path))
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|_| options.output_path(SUMMARY_FILE))
+        .and_then(|path| summary.save(&path))
+        .and_then(|_| lineage.export(&options))
+        .and_then(|_| match &log {
+            Some(log) => options
+                .output_path(EVENTS_FILE)
+                .and_then(|path| log.save(&path)),
+            None => Ok(()),
+        });
+    match result {
+        Ok(()) => {
+            info!("results written to {}", options.output_dir.display());
+            exit.write(AppExit::Success);
+        }
+        Err(err) => {
+            error!("failed to write results: {}", err);
+            exit.write(AppExit::error());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.seed, None);
+        assert_eq!(options.world_size, 64);
+        assert_eq!(options.fixed_hz, 60.0);
+        assert!(!options.is_headless);
+        assert!(!options.is_snapshot_saved);
+    }
+
+    #[test]
+    fn separate_and_inline_values() {
+        let options = parse(&[
+            "--seed",
+            "42",
+            "--world-size=128",
+            "--duration",
+            "3600",
+            "--output=runs/42",
+            "--headless",
+            "--save-snapshot",
+            "--threads",
+            "2",
+        ])
+        .unwrap();
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.world_size, 128);
+        assert_eq!(options.world_size_pow(), 7);
+        assert_eq!(options.duration, Some(3600.0));
+        assert_eq!(options.output_dir, PathBuf::from("runs/42"));
+        assert!(options.is_headless);
+        assert!(options.is_snapshot_saved);
+        assert_eq!(options.threads, Some(2));
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        for args in [
+            &["--unknown"][..],
+            &["--seed"],
+            &["--seed", "-1"],
+            &["--world-size", "100"],
+            &["--world-size", "8"],
+            &["--world-size", "2048"],
+            &["--fixed-hz", "0"],
+            &["--fixed-hz=nan"],
+            &["--duration", "-5"],
+            &["--threads", "0"],
+            &["--remote", "70000"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?}");
+        }
+    }
+}
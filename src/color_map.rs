@@ -1,5 +1,6 @@
-use bevy::color::{LinearRgba};
+use bevy::color::{LinearRgba, Srgba};
 use bevy::prelude::StableInterpolate;
+use std::path::Path;
 
 const INCANDESCENT: [LinearRgba; 11] = [
     LinearRgba::rgb(0.807843137254902, 1.0, 1.0),
@@ -56,24 +57,222 @@ const RAINBOW: [LinearRgba; 34] = [
 
 const RAINBOW_BAD_DATA: LinearRgba = LinearRgba::rgb(0.4, 0.4, 0.4);
 
-#[allow(dead_code)]
+// the published tables below are in sRGB and converted when a color map is created
+const VIRIDIS: [Srgba; 11] = [
+    Srgba::rgb(0.267004, 0.004874, 0.329415),
+    Srgba::rgb(0.282623, 0.140926, 0.457517),
+    Srgba::rgb(0.253935, 0.265254, 0.529983),
+    Srgba::rgb(0.206756, 0.371758, 0.553117),
+    Srgba::rgb(0.163625, 0.471133, 0.558148),
+    Srgba::rgb(0.127568, 0.566949, 0.550556),
+    Srgba::rgb(0.134692, 0.658636, 0.517649),
+    Srgba::rgb(0.266941, 0.748751, 0.440573),
+    Srgba::rgb(0.477504, 0.821444, 0.318195),
+    Srgba::rgb(0.741388, 0.873449, 0.149561),
+    Srgba::rgb(0.993248, 0.906157, 0.143936),
+];
+
+const MAGMA: [Srgba; 11] = [
+    Srgba::rgb(0.001462, 0.000466, 0.013866),
+    Srgba::rgb(0.078815, 0.054184, 0.211667),
+    Srgba::rgb(0.232077, 0.059889, 0.437695),
+    Srgba::rgb(0.390384, 0.100379, 0.501864),
+    Srgba::rgb(0.550287, 0.161158, 0.505719),
+    Srgba::rgb(0.716387, 0.214982, 0.47529),
+    Srgba::rgb(0.868793, 0.287728, 0.409303),
+    Srgba::rgb(0.967671, 0.439703, 0.35981),
+    Srgba::rgb(0.994738, 0.62435, 0.427397),
+    Srgba::rgb(0.995131, 0.812104, 0.572401),
+    Srgba::rgb(0.987053, 0.991438, 0.749504),
+];
+
+const CIVIDIS: [Srgba; 11] = [
+    Srgba::rgb(0.0, 0.135112, 0.304751),
+    Srgba::rgb(0.0, 0.207611, 0.440396),
+    Srgba::rgb(0.231674, 0.285383, 0.423609),
+    Srgba::rgb(0.340836, 0.364886, 0.427339),
+    Srgba::rgb(0.439183, 0.443434, 0.450985),
+    Srgba::rgb(0.541216, 0.529161, 0.474563),
+    Srgba::rgb(0.650806, 0.616549, 0.460162),
+    Srgba::rgb(0.767655, 0.707979, 0.424508),
+    Srgba::rgb(0.890035, 0.804617, 0.36431),
+    Srgba::rgb(0.99404, 0.907103, 0.238286),
+    Srgba::rgb(0.995737, 0.909344, 0.217772),
+];
+
+// blue for negative and red for positive values, meant to be used with a range symmetric around 0
+const BLUE_RED: [Srgba; 11] = [
+    Srgba::rgb(0.019608, 0.188235, 0.380392),
+    Srgba::rgb(0.129412, 0.4, 0.67451),
+    Srgba::rgb(0.262745, 0.576471, 0.764706),
+    Srgba::rgb(0.572549, 0.772549, 0.870588),
+    Srgba::rgb(0.819608, 0.898039, 0.941176),
+    Srgba::rgb(0.968627, 0.968627, 0.968627),
+    Srgba::rgb(0.992157, 0.858824, 0.780392),
+    Srgba::rgb(0.956863, 0.647059, 0.509804),
+    Srgba::rgb(0.839216, 0.376471, 0.301961),
+    Srgba::rgb(0.698039, 0.094118, 0.168627),
+    Srgba::rgb(0.403922, 0.0, 0.121569),
+];
+
+// distinct colors for discrete values
+const CATEGORICAL: [Srgba; 10] = [
+    Srgba::rgb(0.305882, 0.47451, 0.654902),
+    Srgba::rgb(0.94902, 0.556863, 0.168627),
+    Srgba::rgb(0.882353, 0.341176, 0.34902),
+    Srgba::rgb(0.462745, 0.717647, 0.698039),
+    Srgba::rgb(0.34902, 0.631373, 0.309804),
+    Srgba::rgb(0.929412, 0.788235, 0.282353),
+    Srgba::rgb(0.690196, 0.478431, 0.631373),
+    Srgba::rgb(1.0, 0.615686, 0.654902),
+    Srgba::rgb(0.611765, 0.458824, 0.372549),
+    Srgba::rgb(0.729412, 0.690196, 0.67451),
+];
+
+// fallback for a custom scheme which is not loaded
+const GREYSCALE: [LinearRgba; 2] = [LinearRgba::BLACK, LinearRgba::WHITE];
+
+// lower bound of log scaled maps relative to the maximum
+const LOG_SCALE_MIN_FRACTION: f32 = 1e-3;
+
+const DEFAULT_BAD_DATA: LinearRgba = LinearRgba::rgb(1.0, 0.0, 1.0);
+
+// number of colors a custom color map is resampled to
+const CUSTOM_RESOLUTION: usize = 256;
+
+#[derive(Default, PartialEq, Copy, Clone, Debug)]
 pub enum ColorScheme {
+    #[default]
     Incandescent,
     Rainbow,
+    Viridis,
+    Magma,
+    Cividis,
+    BlueRed,
+    Categorical,
+    // colors loaded with load_color_map
+    Custom,
 }
 
-fn get_color_scheme(color_scheme: ColorScheme) -> (&'static [LinearRgba], &'static LinearRgba) {
+impl ColorScheme {
+    pub const ALL: [ColorScheme; 8] = [
+        ColorScheme::Incandescent,
+        ColorScheme::Rainbow,
+        ColorScheme::Viridis,
+        ColorScheme::Magma,
+        ColorScheme::Cividis,
+        ColorScheme::BlueRed,
+        ColorScheme::Categorical,
+        ColorScheme::Custom,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorScheme::Incandescent => "incandescent",
+            ColorScheme::Rainbow => "rainbow",
+            ColorScheme::Viridis => "viridis",
+            ColorScheme::Magma => "magma",
+            ColorScheme::Cividis => "cividis",
+            ColorScheme::BlueRed => "blue-red (diverging)",
+            ColorScheme::Categorical => "categorical",
+            ColorScheme::Custom => "custom",
+        }
+    }
+
+    pub fn is_diverging(self) -> bool {
+        self == ColorScheme::BlueRed
+    }
+}
+
+fn to_linear(colors: &[Srgba]) -> Vec<LinearRgba> {
+    colors.iter().map(|&color| LinearRgba::from(color)).collect()
+}
+
+fn get_color_scheme(color_scheme: ColorScheme) -> (Vec<LinearRgba>, LinearRgba) {
     match color_scheme {
-        ColorScheme::Incandescent => (&INCANDESCENT, &INCANDESCENT_BAD_DATA),
-        ColorScheme::Rainbow => (&RAINBOW, &RAINBOW_BAD_DATA),
+        ColorScheme::Incandescent => (INCANDESCENT.into(), INCANDESCENT_BAD_DATA),
+        ColorScheme::Rainbow => (RAINBOW.into(), RAINBOW_BAD_DATA),
+        ColorScheme::Viridis => (to_linear(&VIRIDIS), DEFAULT_BAD_DATA),
+        ColorScheme::Magma => (to_linear(&MAGMA), DEFAULT_BAD_DATA),
+        ColorScheme::Cividis => (to_linear(&CIVIDIS), DEFAULT_BAD_DATA),
+        ColorScheme::BlueRed => (to_linear(&BLUE_RED), DEFAULT_BAD_DATA),
+        ColorScheme::Categorical => (to_linear(&CATEGORICAL), DEFAULT_BAD_DATA),
+        ColorScheme::Custom => (GREYSCALE.into(), DEFAULT_BAD_DATA),
     }
 }
 
+// Load a color map from a csv file with one control point per line.
+// A line is either "r,g,b" for evenly spaced colors or "position,r,g,b" with positions in [0,1].
+// Components are sRGB in [0,1] or, if any is larger than 1, in [0,255].
+// Empty lines, comments starting with # and a header are ignored.
+pub fn load_color_map(path: &Path) -> std::io::Result<Vec<LinearRgba>> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+    let mut rows: Vec<Vec<f32>> = Vec::new();
+    for (line_idx, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values: Result<Vec<f32>, _> = line.split(',').map(|v| v.trim().parse()).collect();
+        match values {
+            Ok(values) if values.len() == 3 || values.len() == 4 => rows.push(values),
+            Ok(_) => return Err(invalid(format!("line {}: expected 3 or 4 values", line_idx + 1))),
+            // header
+            Err(_) if rows.is_empty() => continue,
+            Err(err) => return Err(invalid(format!("line {}: {}", line_idx + 1, err))),
+        }
+    }
+    if rows.len() < 2 {
+        return Err(invalid("at least two control points are required".to_string()));
+    }
+
+    let is_u8 = rows.iter().any(|row| row[row.len() - 3..].iter().any(|&c| c > 1.0));
+    let color_scale = if is_u8 { 1.0 / 255.0 } else { 1.0 };
+    let num_rows = rows.len();
+    let mut control_points: Vec<(f32, LinearRgba)> = rows
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let pos = if row.len() == 4 {
+                row[0]
+            } else {
+                i as f32 / (num_rows - 1) as f32
+            };
+            let [r, g, b] = [row[row.len() - 3], row[row.len() - 2], row[row.len() - 1]];
+            let color = Srgba::rgb(r * color_scale, g * color_scale, b * color_scale);
+            (pos, LinearRgba::from(color))
+        })
+        .collect();
+    control_points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // resample to evenly spaced colors
+    let (first, last) = (control_points[0].0, control_points[num_rows - 1].0);
+    let colors = (0..CUSTOM_RESOLUTION)
+        .map(|i| {
+            let pos = first + (last - first) * i as f32 / (CUSTOM_RESOLUTION - 1) as f32;
+            let upper = control_points
+                .iter()
+                .position(|(p, _)| *p >= pos)
+                .unwrap_or(num_rows - 1)
+                .max(1);
+            let (p0, c0) = control_points[upper - 1];
+            let (p1, c1) = control_points[upper];
+            let t = if p1 > p0 { (pos - p0) / (p1 - p0) } else { 0.0 };
+            c0.interpolate_stable(&c1, t.clamp(0.0, 1.0))
+        })
+        .collect();
+    Ok(colors)
+}
+
 pub struct ColorMap {
     min: f32,
     max: f32,
     colors: Vec<LinearRgba>,
     bad_data_color: LinearRgba,
+    // colors are not interpolated but assigned to equally sized bins
+    is_discrete: bool,
+    is_log_scale: bool,
 }
 
 impl ColorMap {
@@ -82,8 +281,40 @@ impl ColorMap {
         ColorMap {
             min: min,
             max: max,
-            colors: colors.into(),
-            bad_data_color: *bad_color,
+            colors,
+            bad_data_color: bad_color,
+            is_discrete: color_scheme == ColorScheme::Categorical,
+            is_log_scale: false,
+        }
+    }
+
+    // Color map with evenly spaced colors, e.g. from load_color_map.
+    pub fn from_colors(min: f32, max: f32, colors: Vec<LinearRgba>) -> Self {
+        ColorMap {
+            min,
+            max,
+            colors,
+            bad_data_color: DEFAULT_BAD_DATA,
+            is_discrete: false,
+            is_log_scale: false,
+        }
+    }
+
+    // Map values logarithmically. The bounds are clamped to positive values, the minimum to at
+    // least a small fraction of max, e.g. for fields which are all zero.
+    pub fn with_log_scale(mut self) -> Self {
+        self.is_log_scale = true;
+        self.max = self.max.max(f32::MIN_POSITIVE);
+        self.min = self.min.max(self.max * LOG_SCALE_MIN_FRACTION);
+        self
+    }
+
+    // Value which is mapped to the relative position t in [0,1] of the color map.
+    pub fn value_at(&self, t: f32) -> f32 {
+        if self.is_log_scale {
+            self.min * (self.max / self.min).powf(t)
+        } else {
+            self.min + t * (self.max - self.min)
         }
     }
 
@@ -98,8 +329,19 @@ impl ColorMap {
         }
 
         // scale value to [0,1]
-        let v_scaled = ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+        let v_scaled = if self.is_log_scale {
+            if value <= 0.0 {
+                return self.colors[0];
+            }
+            ((value / self.min).ln() / (self.max / self.min).ln()).clamp(0.0, 1.0)
+        } else {
+            ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0)
+        };
         //let v_scaled = (value.clamp(self.min, self.max) - self.min) / self.max;
+        if self.is_discrete {
+            let idx = (v_scaled * self.colors.len() as f32) as usize;
+            return self.colors[idx.min(self.colors.len() - 1)];
+        }
         let v_select = v_scaled * (self.colors.len() - 1) as f32;
         let lower_idx = v_select.trunc() as usize;
         let upper_idx = std::cmp::min(lower_idx + 1, self.colors.len() - 1);
@@ -108,3 +350,70 @@ impl ColorMap {
         self.colors[lower_idx].interpolate_stable(&self.colors[upper_idx], t)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::color::ColorToComponents;
+
+    fn load(name: &str, text: &str) -> std::io::Result<Vec<LinearRgba>> {
+        let path = std::env::temp_dir().join(format!("eco-sim-{}-{name}.csv", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let colors = load_color_map(&path);
+        std::fs::remove_file(&path).unwrap();
+        colors
+    }
+
+    fn assert_color(color: LinearRgba, srgb: [f32; 3]) {
+        let expected = LinearRgba::from(Srgba::rgb(srgb[0], srgb[1], srgb[2]));
+        assert!(
+            (color.to_vec4() - expected.to_vec4()).abs().max_element() < 1e-4,
+            "{color:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn log_scale_of_non_positive_values_is_finite() {
+        for (min, max) in [(0.0, 0.0), (-1.0, 0.0), (0.0, 2.0), (-3.0, -1.0)] {
+            let color_map = ColorMap::new(min, max, ColorScheme::Viridis).with_log_scale();
+            for t in [0.0, 0.5, 1.0] {
+                let value = color_map.value_at(t);
+                assert!(value.is_finite() && value > 0.0, "{min}..{max}: {value}");
+            }
+            for value in [-1.0, 0.0, 1e-6, 1.0, 10.0] {
+                let color = color_map.get_color(value);
+                assert!(color.to_vec4().is_finite(), "{min}..{max}: {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn evenly_spaced_colors_are_interpolated() {
+        let colors = load("even", "# comment\nr,g,b\n\n0,0,0\n1,1,1\n").unwrap();
+        assert_eq!(colors.len(), CUSTOM_RESOLUTION);
+        assert_color(colors[0], [0.0, 0.0, 0.0]);
+        assert_color(colors[CUSTOM_RESOLUTION - 1], [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn control_points_are_sorted_by_position() {
+        let colors = load(
+            "positions",
+            "1.0, 0, 0, 255\n0.0, 255, 0, 0\n0.5, 0, 255, 0\n",
+        )
+        .unwrap();
+        assert_color(colors[0], [1.0, 0.0, 0.0]);
+        assert_color(colors[CUSTOM_RESOLUTION - 1], [0.0, 0.0, 1.0]);
+        // [0,255] components are scaled
+        let middle = colors[(CUSTOM_RESOLUTION - 1) / 2].to_vec4();
+        assert!(middle.y > middle.x && middle.y > middle.z);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(load("single", "0,0,0\n").is_err());
+        assert!(load("count", "0,0,0\n1,1\n").is_err());
+        assert!(load("value", "0,0,0\n1,x,1\n").is_err());
+        assert!(load("empty", "").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Contour lines of a scalar field drawn on top of the terrain.
+//! The field and the interval between two lines are chosen in a panel toggled with F3.
+//! Any scalar field of the [`FieldVisRegistry`] can be used.
+
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+
+use crate::field_vis::{FieldVisRegistry, VisFieldKind};
+use crate::terrain::{Surface, Terrain};
+
+// time between two recomputations of the lines while the field is changing [s]
+const UPDATE_INTERVAL: f32 = 1.0;
+// lift of the lines above the terrain to avoid z-fighting
+const HEIGHT_OFFSET: f32 = 0.02;
+
+#[derive(Resource)]
+pub struct ContourState {
+    // index of the field in the registry, None disables the contour lines
+    pub field: Option<usize>,
+    // value difference between two neighboring lines
+    pub interval: f32,
+    pub color: Color,
+    // field and interval the segments were computed for
+    computed_for: Option<(usize, f32)>,
+    next_update: f32,
+    segments: Vec<[Vec3; 2]>,
+    is_panel_visible: bool,
+}
+
+impl Default for ContourState {
+    fn default() -> Self {
+        ContourState {
+            field: None,
+            interval: 0.25,
+            color: Color::srgb(0.1, 0.1, 0.1),
+            computed_for: None,
+            next_update: 0.0,
+            segments: Vec::new(),
+            is_panel_visible: false,
+        }
+    }
+}
+
+pub struct ContourPlugin;
+
+impl Plugin for ContourPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FieldVisRegistry>()
+            .init_resource::<ContourState>()
+            .add_systems(Update, contour_system)
+            .add_systems(EguiPrimaryContextPass, contour_ui_system);
+    }
+}
+
+pub fn contour_system(
+    field_query: Query<(&Terrain, &Surface)>,
+    mut contour_state: ResMut<ContourState>,
+    registry: Res<FieldVisRegistry>,
+    time: Res<Time>,
+    mut gizmos: Gizmos,
+) {
+    let Some(field_idx) = contour_state.field else {
+        contour_state.computed_for = None;
+        contour_state.segments.clear();
+        return;
+    };
+    let Some(VisFieldKind::Scalar { field, .. }) = registry.get(field_idx).map(|f| &f.kind) else {
+        return;
+    };
+
+    let settings = Some((field_idx, contour_state.interval));
+    if contour_state.computed_for != settings || time.elapsed_secs() >= contour_state.next_update {
+        let (terrain, surface) = field_query.single().unwrap();
+        let height_map = &terrain.height_map;
+        let state = &mut *contour_state;
+        state.computed_for = settings;
+        state.next_update = time.elapsed_secs() + UPDATE_INTERVAL;
+        state.segments = field(terrain, surface)
+            .contour_lines(state.interval)
+            .into_iter()
+            .map(|segment| {
+                segment.map(|p| Vec3::new(p.x, height_map.get_bilinear(p) + HEIGHT_OFFSET, p.y))
+            })
+            .collect();
+    }
+
+    for [start, end] in &contour_state.segments {
+        gizmos.line(*start, *end, contour_state.color);
+    }
+}
+
+pub fn contour_ui_system(
+    mut contexts: EguiContexts,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut contour_state: ResMut<ContourState>,
+    registry: Res<FieldVisRegistry>,
+) -> Result {
+    if key_input.just_pressed(KeyCode::F3) {
+        contour_state.is_panel_visible = !contour_state.is_panel_visible;
+    }
+
+    if !contour_state.is_panel_visible {
+        return Ok(());
+    }
+
+    let state = &mut *contour_state;
+    egui::Window::new("Contour lines")
+        .resizable(false)
+        .anchor(Align2::LEFT_TOP, egui::vec2(5.0, 40.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            let selected_name = state
+                .field
+                .and_then(|i| registry.get(i))
+                .map_or("none", |f| f.name);
+            egui::ComboBox::from_label("field")
+                .selected_text(selected_name)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.field, None, "none");
+                    for (idx, field) in registry.iter().enumerate() {
+                        if let VisFieldKind::Scalar { .. } = field.kind {
+                            ui.selectable_value(&mut state.field, Some(idx), field.name);
+                        }
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.label("interval");
+                ui.add(
+                    egui::DragValue::new(&mut state.interval)
+                        .speed(0.01)
+                        .range(0.001..=f32::MAX),
+                );
+            });
+            let mut color = state.color.to_srgba().to_f32_array_no_alpha();
+            ui.horizontal(|ui| {
+                ui.label("color");
+                ui.color_edit_button_rgb(&mut color);
+            });
+            state.color = Color::srgb_from_array(color);
+        });
+
+    Ok(())
+}
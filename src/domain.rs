@@ -1,35 +1,76 @@
 use bevy::math::{FloatPow, USizeVec2, usizevec2};
 use bevy::prelude::*;
 use num_traits::{Bounded, NumAssign};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::ops::{Add, Index, IndexMut, Mul};
+use std::path::Path;
+use std::sync::OnceLock;
 
-const SIZE_POW: USizeVec2 = USizeVec2 { x: 6, y: 6 };
-pub const SIZE: USizeVec2 = USizeVec2 {
-    x: 1 << (SIZE_POW.x),
-    y: 1 << (SIZE_POW.y),
-};
-pub const SIZE_F32: Vec2 = Vec2 {
-    x: SIZE.x as f32,
-    y: SIZE.y as f32,
-};
-pub const HALF_SIZE: USizeVec2 = USizeVec2 {
-    x: SIZE.x >> 1,
-    y: SIZE.y >> 1,
-};
-
-pub const BOUNDS: Rect = Rect {
-    min: Vec2 { x: 0.0, y: 0.0 },
-    max: Vec2 {
-        x: SIZE.x as f32,
-        y: SIZE.y as f32,
-    },
-};
+const DEFAULT_SIZE_POW: u32 = 6;
+// the domain is square with a side length of 2^SIZE_POW, set once at startup
+static SIZE_POW: OnceLock<u32> = OnceLock::new();
 
+// Set the side length of the domain to 2^size_pow. Has to be called before any field is created.
+// Setting the same size again is allowed, e.g. for another app in the same process, a different
+// one fails.
+pub fn set_size_pow(size_pow: u32) -> Result<(), String> {
+    let current = *SIZE_POW.get_or_init(|| size_pow);
+    if current != size_pow {
+        return Err(format!(
+            "the world size {} differs from the size {} already used in this process",
+            1u32 << size_pow,
+            1u32 << current
+        ));
+    }
+    Ok(())
+}
+
+fn size_pow() -> USizeVec2 {
+    USizeVec2::splat(*SIZE_POW.get_or_init(|| DEFAULT_SIZE_POW) as usize)
+}
+
+pub fn size() -> USizeVec2 {
+    let size_pow = size_pow();
+    USizeVec2::new(1 << size_pow.x, 1 << size_pow.y)
+}
+
+pub fn size_f32() -> Vec2 {
+    size().as_vec2()
+}
+
+pub fn half_size() -> USizeVec2 {
+    size() >> 1
+}
+
+pub fn bounds() -> Rect {
+    Rect::from_corners(Vec2::ZERO, size_f32())
+}
+
+// Rectangle of grid points, min is inclusive and max exclusive.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Region {
+    pub min: USizeVec2,
+    pub max: USizeVec2,
+}
+
+impl Region {
+    pub fn union(self, other: Region) -> Region {
+        Region {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct Field<T> {
     buffer: Vec<T>,
     //    subdivisions: i32,
     pub idx_scale: f32,
     pub size: USizeVec2,
+    // bounding box of all changes since the last clear_dirty
+    dirty: Option<Region>,
 }
 /*
 trait FieldType:
@@ -39,8 +80,8 @@ trait FieldType:
 impl<T: Default + Copy + NumAssign> Field<T> {
     pub fn new(subdivisions: i32) -> Self {
         let size = USizeVec2::new(
-            1 << (SIZE_POW.x as i32 + subdivisions) as usize,
-            1 << (SIZE_POW.y as i32 + subdivisions) as usize,
+            1 << (size_pow().x as i32 + subdivisions) as usize,
+            1 << (size_pow().y as i32 + subdivisions) as usize,
         );
 
         Field {
@@ -48,6 +89,7 @@ impl<T: Default + Copy + NumAssign> Field<T> {
             //       subdivisions: subdivisions,
             idx_scale: 2.0_f32.powf(subdivisions as f32),
             size: size,
+            dirty: None,
         }
     }
 
@@ -63,17 +105,145 @@ impl<T: Default + Copy + NumAssign> Field<T> {
         idx[0] + idx[1] * self.size.x
     }*/
 
-    #[allow(dead_code)]
+    // index of the grid point closest to pos
+    pub fn nearest_index(&self, pos: Vec2) -> USizeVec2 {
+        self.clamp_index((pos * self.idx_scale).round().as_usizevec2())
+    }
+
     pub fn get_nearest(&self, pos: Vec2) -> T {
-        let idx = self.clamp_index((pos * self.idx_scale).round().as_usizevec2());
-        self.buffer[self.flat_index(idx)]
+        self.buffer[self.flat_index(self.nearest_index(pos))]
     }
 
     // flat size
-    #[allow(dead_code)]
     pub fn num_elem(&self) -> usize {
         self.buffer.len()
     }
+
+    pub fn fill(&mut self, value: T) {
+        self.buffer.fill(value);
+        self.mark_dirty(Region {
+            min: USizeVec2::ZERO,
+            max: self.size,
+        });
+    }
+
+    pub fn mark_dirty(&mut self, region: Region) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(region),
+            None => region,
+        });
+    }
+
+    // Region which changed since the last call of clear_dirty.
+    pub fn dirty_region(&self) -> Option<Region> {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = None;
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.buffer.iter()
+    }
+
+    // Copy the values of a field with the same size. Only the changed values are marked dirty.
+    pub fn copy_from(&mut self, other: &Field<T>) {
+        assert_eq!(self.size, other.size, "the fields have different sizes");
+        let size_x = self.size.x;
+        let mut changed: Option<Region> = None;
+        for (i, (value, &new)) in self.buffer.iter_mut().zip(&other.buffer).enumerate() {
+            if *value == new {
+                continue;
+            }
+            *value = new;
+            let idx = usizevec2(i % size_x, i / size_x);
+            let region = Region {
+                min: idx,
+                max: idx + USizeVec2::ONE,
+            };
+            changed = Some(changed.map_or(region, |changed| changed.union(region)));
+        }
+        if let Some(region) = changed {
+            self.mark_dirty(region);
+        }
+    }
+
+    // Mutable access to all values in row-major order. The whole field is marked dirty once
+    // instead of per value as with the index operators.
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.mark_dirty(Region {
+            min: USizeVec2::ZERO,
+            max: self.size,
+        });
+        self.buffer.iter_mut()
+    }
+
+    // Grid points within radius of pos as (local position, squared local radius, min, max index).
+    fn kernel_bounds(&self, pos: Vec2, radius: f32) -> (Vec2, f32, USizeVec2, USizeVec2) {
+        let pos_local = pos * Vec2::splat(self.idx_scale);
+        let r_local = radius * self.idx_scale;
+        let min_pos_local = pos_local - Vec2::splat(r_local);
+        let max_pos_local = pos_local + Vec2::splat(r_local);
+        // +1 because the contribution at the min_idx is always 0
+        let min_idx = self.clamp_index(min_pos_local.ceil().as_usizevec2());
+        // upper bound is size (inclusive) because the loop is exclusive
+        let max_idx = max_pos_local
+            .ceil()
+            .as_usizevec2()
+            .clamp(USizeVec2::ZERO, self.size);
+        (pos_local, r_local.squared(), min_idx, max_idx)
+    }
+
+    // Region of grid points which are affected by a kernel at pos.
+    pub fn kernel_region(&self, pos: Vec2, radius: f32) -> Option<Region> {
+        let (_, _, min, max) = self.kernel_bounds(pos, radius);
+        min.cmplt(max).all().then_some(Region { min, max })
+    }
+
+    // Values inside of the region in row-major order.
+    pub fn read_region(&self, region: Region) -> Vec<T> {
+        (region.min.y..region.max.y)
+            .flat_map(|y| {
+                let start = self.flat_index(usizevec2(region.min.x, y));
+                self.buffer[start..start + region.max.x - region.min.x]
+                    .iter()
+                    .copied()
+            })
+            .collect()
+    }
+
+    // Overwrite the region with values from read_region.
+    pub fn write_region(&mut self, region: Region, values: &[T]) {
+        let width = region.max.x - region.min.x;
+        for (row, y) in values.chunks_exact(width).zip(region.min.y..region.max.y) {
+            let start = self.flat_index(usizevec2(region.min.x, y));
+            self.buffer[start..start + width].copy_from_slice(row);
+        }
+        self.mark_dirty(region);
+    }
+
+    // Apply f to each value within radius of pos together with the weight (1 - d²/r²)
+    // of the quadratic kernel.
+    pub fn apply_kernel(&mut self, pos: Vec2, radius: f32, mut f: impl FnMut(&mut T, f32)) {
+        let (pos_local, r_sq, min_idx, max_idx) = self.kernel_bounds(pos, radius);
+        if min_idx.cmplt(max_idx).all() {
+            self.mark_dirty(Region {
+                min: min_idx,
+                max: max_idx,
+            });
+        }
+
+        for iy in min_idx.y..max_idx.y {
+            for ix in min_idx.x..max_idx.x {
+                let d_sq = pos_local.distance_squared(vec2(ix as f32, iy as f32));
+                if d_sq < r_sq {
+                    let flat_idx = self.flat_index(USizeVec2::new(ix, iy));
+                    f(&mut self.buffer[flat_idx], 1.0 - d_sq / r_sq);
+                }
+            }
+        }
+    }
 }
 
 impl<T: Default + Copy + NumAssign + Mul<f32, Output = T> + Add<T, Output = T>> Field<T> {
@@ -96,6 +266,117 @@ impl<T: Default + Copy + NumAssign + Mul<f32, Output = T> + Add<T, Output = T>>
     }
 }
 
+impl Field<f32> {
+    // Gradient at the grid point idx from (one-sided at the boundary) central differences.
+    pub fn gradient(&self, idx: USizeVec2) -> Vec2 {
+        let lower = self.clamp_index(idx.saturating_sub(USizeVec2::ONE));
+        let upper = self.clamp_index(idx + USizeVec2::ONE);
+        let dx = self.buffer[self.flat_index(usizevec2(upper.x, idx.y))]
+            - self.buffer[self.flat_index(usizevec2(lower.x, idx.y))];
+        let dy = self.buffer[self.flat_index(usizevec2(idx.x, upper.y))]
+            - self.buffer[self.flat_index(usizevec2(idx.x, lower.y))];
+        // distances in domain units
+        let dist = (upper - lower).as_vec2().max(Vec2::ONE) / self.idx_scale;
+        vec2(dx, dy) / dist
+    }
+
+    // Move each value within radius of pos towards the mean of its four neighbors,
+    // by amount in [0,1] weighted with the quadratic kernel.
+    pub fn smooth_kernel(&mut self, pos: Vec2, radius: f32, amount: f32) {
+        let (pos_local, r_sq, min_idx, max_idx) = self.kernel_bounds(pos, radius);
+        let mut smoothed = Vec::new();
+        for iy in min_idx.y..max_idx.y {
+            for ix in min_idx.x..max_idx.x {
+                let d_sq = pos_local.distance_squared(vec2(ix as f32, iy as f32));
+                if d_sq >= r_sq {
+                    continue;
+                }
+                let idx = usizevec2(ix, iy);
+                let lower = self.clamp_index(idx.saturating_sub(USizeVec2::ONE));
+                let upper = self.clamp_index(idx + USizeVec2::ONE);
+                let mean = (self.buffer[self.flat_index(usizevec2(lower.x, iy))]
+                    + self.buffer[self.flat_index(usizevec2(upper.x, iy))]
+                    + self.buffer[self.flat_index(usizevec2(ix, lower.y))]
+                    + self.buffer[self.flat_index(usizevec2(ix, upper.y))])
+                    / 4.0;
+                let value = self.buffer[self.flat_index(idx)];
+                let t = (amount * (1.0 - d_sq / r_sq)).clamp(0.0, 1.0);
+                smoothed.push((idx, value + (mean - value) * t));
+            }
+        }
+
+        for (idx, value) in smoothed {
+            self[[idx.x, idx.y]] = value;
+        }
+    }
+
+    // Iso-lines at all multiples of interval as line segments in domain coordinates,
+    // computed with marching squares.
+    pub fn contour_lines(&self, interval: f32) -> Vec<[Vec2; 2]> {
+        let mut segments = Vec::new();
+        if interval <= 0.0 {
+            return segments;
+        }
+
+        for y in 0..self.size.y.saturating_sub(1) {
+            for x in 0..self.size.x.saturating_sub(1) {
+                // corners in counterclockwise order
+                let corners = [
+                    usizevec2(x, y),
+                    usizevec2(x + 1, y),
+                    usizevec2(x + 1, y + 1),
+                    usizevec2(x, y + 1),
+                ];
+                let values = corners.map(|idx| self.buffer[self.flat_index(idx)]);
+                let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+                let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                if !min.is_finite() || !max.is_finite() {
+                    continue;
+                }
+
+                let first_level = (min / interval).ceil() as i32;
+                let last_level = (max / interval).floor() as i32;
+                for level in first_level..=last_level {
+                    let level = level as f32 * interval;
+                    let is_above = values.map(|v| v >= level);
+                    // crossing of each edge i between corner i and i+1
+                    let crossings: [Option<Vec2>; 4] = std::array::from_fn(|i| {
+                        let j = (i + 1) % 4;
+                        if is_above[i] == is_above[j] {
+                            return None;
+                        }
+                        let t = (level - values[i]) / (values[j] - values[i]);
+                        let pos = corners[i].as_vec2().lerp(corners[j].as_vec2(), t);
+                        Some(pos / self.idx_scale)
+                    });
+
+                    match crossings {
+                        [Some(e0), Some(e1), Some(e2), Some(e3)] => {
+                            // saddle point: disambiguate with the value at the center
+                            let center = values.iter().sum::<f32>() / 4.0;
+                            if (center >= level) == is_above[0] {
+                                segments.push([e0, e1]);
+                                segments.push([e2, e3]);
+                            } else {
+                                segments.push([e3, e0]);
+                                segments.push([e1, e2]);
+                            }
+                        }
+                        _ => {
+                            let mut points = crossings.into_iter().flatten();
+                            if let (Some(a), Some(b)) = (points.next(), points.next()) {
+                                segments.push([a, b]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        segments
+    }
+}
+
 impl<T: Copy + Bounded + std::cmp::PartialOrd> Field<T> {
     pub fn compute_min_max(&self) -> (T, T) {
         let min = self.buffer.iter().fold(T::max_value(), |a, &b| {
@@ -116,30 +397,26 @@ impl<T: Copy + Bounded + std::cmp::PartialOrd> Field<T> {
     }
 }
 
-impl<T: Default + Copy + NumAssign + Mul<f32, Output = T>> Field<T> {
-    pub fn add_kernel(&mut self, pos: Vec2, radius: f32, value: T) {
-        let pos_local = pos * Vec2::splat(self.idx_scale);
-        let r_local = radius * self.idx_scale;
-        let min_pos_local = pos_local - Vec2::splat(r_local);
-        let max_pos_local = pos_local + Vec2::splat(r_local);
-        // +1 because the contribution at the min_idx is always 0
-        let min_idx = self.clamp_index(min_pos_local.ceil().as_usizevec2());
-        // upper bound is size (inclusive) because the loop is exclusive
-        let max_idx = max_pos_local
-            .ceil()
-            .as_usizevec2()
-            .clamp(USizeVec2::ZERO, self.size);
-
-        let r_sq = r_local.squared();
-        for iy in min_idx.y..max_idx.y {
-            for ix in min_idx.x..max_idx.x {
-                let d_sq = pos_local.distance_squared(vec2(ix as f32, iy as f32));
-                if d_sq < r_sq {
-                    let flat_idx = self.flat_index(USizeVec2::new(ix, iy));
-                    self.buffer[flat_idx] += value * (1.0 - d_sq / r_sq);
+impl<T: Copy + std::fmt::Display> Field<T> {
+    // Write the values as a grid with one line per row.
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for row in self.buffer.chunks_exact(self.size.x) {
+            for (x, value) in row.iter().enumerate() {
+                if x > 0 {
+                    write!(writer, ",")?;
                 }
+                write!(writer, "{}", value)?;
             }
+            writeln!(writer)?;
         }
+        writer.flush()
+    }
+}
+
+impl<T: Default + Copy + NumAssign + Mul<f32, Output = T>> Field<T> {
+    pub fn add_kernel(&mut self, pos: Vec2, radius: f32, value: T) {
+        self.apply_kernel(pos, radius, |v, weight| *v += value * weight);
     }
 }
 
@@ -153,7 +430,12 @@ impl<T: Default + Copy + NumAssign> Index<[usize; 2]> for Field<T> {
 
 impl<T: Default + Copy + NumAssign> IndexMut<[usize; 2]> for Field<T> {
     fn index_mut(&mut self, index: [usize; 2]) -> &mut T {
-        let flat_idx = self.flat_index(index.into());
+        let idx = index.into();
+        self.mark_dirty(Region {
+            min: idx,
+            max: idx + USizeVec2::ONE,
+        });
+        let flat_idx = self.flat_index(idx);
         &mut self.buffer[flat_idx]
     }
 }
@@ -168,6 +450,25 @@ impl<T: Default + Copy + NumAssign> Index<usize> for Field<T> {
 
 impl<T: Default + Copy + NumAssign> IndexMut<usize> for Field<T> {
     fn index_mut(&mut self, idx: usize) -> &mut T {
+        let idx_2d = usizevec2(idx % self.size.x, idx / self.size.x);
+        self.mark_dirty(Region {
+            min: idx_2d,
+            max: idx_2d + USizeVec2::ONE,
+        });
         &mut self.buffer[idx]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_different_size_is_rejected() {
+        // the unit tests use the default size
+        let size_pow = size_pow().x as u32;
+        assert_eq!(set_size_pow(size_pow), Ok(()));
+        assert!(set_size_pow(size_pow + 1).is_err());
+        assert_eq!(size(), USizeVec2::splat(1 << size_pow));
+    }
+}
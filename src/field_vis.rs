@@ -0,0 +1,565 @@
+//! Overlays which visualize simulation fields on the terrain.
+//! The selected field is rendered into the field image which is used as texture of the terrain.
+//! Fields are made available with [`RegisterVisField::register_vis_field`] and selected in a UI
+//! panel toggled with F2. F1 disables the overlay. While an overlay is active, a legend shows
+//! the colors and the value under the mouse cursor.
+
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+
+use crate::color_map::{self, ColorMap, ColorScheme};
+use crate::domain;
+use crate::player_inputs::TerrainRayCast;
+use crate::terrain::*;
+
+pub type ScalarFieldFn = for<'a> fn(&'a Terrain, &'a Surface) -> &'a domain::Field<f32>;
+pub type CategoricalFieldFn = for<'a> fn(&'a Terrain, &'a Surface) -> &'a domain::Field<u8>;
+
+pub enum VisFieldKind {
+    Scalar {
+        field: ScalarFieldFn,
+        // None uses the min and max values of the field
+        default_range: Option<(f32, f32)>,
+        color_scheme: ColorScheme,
+    },
+    Categorical {
+        field: CategoricalFieldFn,
+        // name and color of each category id
+        categories: Vec<(&'static str, LinearRgba)>,
+    },
+}
+
+pub struct VisField {
+    pub name: &'static str,
+    pub kind: VisFieldKind,
+}
+
+impl VisField {
+    pub fn scalar(name: &'static str, field: ScalarFieldFn) -> Self {
+        VisField {
+            name,
+            kind: VisFieldKind::Scalar {
+                field,
+                default_range: None,
+                color_scheme: ColorScheme::default(),
+            },
+        }
+    }
+
+    pub fn categorical(
+        name: &'static str,
+        field: CategoricalFieldFn,
+        categories: Vec<(&'static str, LinearRgba)>,
+    ) -> Self {
+        VisField {
+            name,
+            kind: VisFieldKind::Categorical { field, categories },
+        }
+    }
+
+    pub fn with_range(mut self, min: f32, max: f32) -> Self {
+        if let VisFieldKind::Scalar { default_range, .. } = &mut self.kind {
+            *default_range = Some((min, max));
+        }
+        self
+    }
+
+    pub fn with_color_scheme(mut self, scheme: ColorScheme) -> Self {
+        if let VisFieldKind::Scalar { color_scheme, .. } = &mut self.kind {
+            *color_scheme = scheme;
+        }
+        self
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct FieldVisRegistry {
+    fields: Vec<VisField>,
+}
+
+impl FieldVisRegistry {
+    pub fn register(&mut self, field: VisField) {
+        self.fields.push(field);
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&VisField> {
+        self.fields.get(idx)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, VisField> {
+        self.fields.iter()
+    }
+}
+
+pub trait RegisterVisField {
+    fn register_vis_field(&mut self, field: VisField) -> &mut Self;
+}
+
+impl RegisterVisField for App {
+    fn register_vis_field(&mut self, field: VisField) -> &mut Self {
+        self.init_resource::<FieldVisRegistry>();
+        self.world_mut()
+            .resource_mut::<FieldVisRegistry>()
+            .register(field);
+        self
+    }
+}
+
+// When the overlay image is recomputed.
+#[derive(Default, PartialEq, Copy, Clone, Debug)]
+pub enum UpdatePolicy {
+    EveryFrame,
+    // whole field after the given number of fixed update ticks
+    FixedTicks(u32),
+    // only the region of the field which changed
+    #[default]
+    DirtyRegion,
+}
+
+impl UpdatePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            UpdatePolicy::EveryFrame => "every frame",
+            UpdatePolicy::FixedTicks(_) => "every n ticks",
+            UpdatePolicy::DirtyRegion => "changed regions",
+        }
+    }
+}
+
+// default interval of UpdatePolicy::FixedTicks
+const DEFAULT_UPDATE_TICKS: u32 = 30;
+
+// Settings which require a full update of the overlay if changed.
+#[derive(PartialEq, Copy, Clone)]
+struct DisplaySettings {
+    field: usize,
+    range: (f32, f32),
+    color_scheme: ColorScheme,
+    log_scale: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct FieldVisState {
+    // index of the selected field in the registry
+    pub active: Option<usize>,
+    pub auto_range: bool,
+    // range currently mapped to the color scheme
+    pub range: (f32, f32),
+    pub color_scheme: ColorScheme,
+    pub log_scale: bool,
+    // colors of ColorScheme::Custom
+    pub custom_colors: Option<Vec<LinearRgba>>,
+    // csv file the custom color map is loaded from
+    custom_path: String,
+    load_error: Option<String>,
+    // position in the domain of the terrain under the mouse cursor
+    pub cursor_pos: Option<Vec2>,
+    pub update_policy: UpdatePolicy,
+    // overlay which is currently applied to the terrain
+    shown: Option<usize>,
+    // settings the current overlay image was computed with
+    drawn_settings: Option<DisplaySettings>,
+    // changes of the field since the last update of the image
+    pending_region: Option<domain::Region>,
+    ticks_since_update: u32,
+    is_panel_visible: bool,
+}
+
+impl FieldVisState {
+    // Select a field and reset the display settings to its defaults.
+    pub fn select(&mut self, registry: &FieldVisRegistry, idx: Option<usize>) {
+        self.active = idx;
+        if let Some(VisFieldKind::Scalar {
+            default_range,
+            color_scheme,
+            ..
+        }) = idx.and_then(|i| registry.get(i)).map(|f| &f.kind)
+        {
+            self.auto_range = default_range.is_none();
+            self.range = default_range.unwrap_or((0.0, 1.0));
+            self.color_scheme = *color_scheme;
+        }
+    }
+
+    // Color map for the current range and display settings.
+    pub fn color_map(&self) -> ColorMap {
+        let (min, max) = self.range;
+        let cmap = match (&self.custom_colors, self.color_scheme) {
+            (Some(colors), ColorScheme::Custom) => ColorMap::from_colors(min, max, colors.clone()),
+            _ => ColorMap::new(min, max, self.color_scheme),
+        };
+        if self.log_scale {
+            cmap.with_log_scale()
+        } else {
+            cmap
+        }
+    }
+
+    pub fn active_field<'a>(&self, registry: &'a FieldVisRegistry) -> Option<&'a VisField> {
+        self.active.and_then(|i| registry.get(i))
+    }
+}
+
+pub struct FieldVisPlugin;
+
+impl Plugin for FieldVisPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FieldVisRegistry>()
+            .init_resource::<FieldVisState>()
+            .add_systems(FixedUpdate, count_ticks_system)
+            .add_systems(Update, (vis_fields_system, cursor_probe_system))
+            .add_systems(
+                EguiPrimaryContextPass,
+                (field_vis_ui_system, field_legend_ui_system),
+            );
+    }
+}
+
+pub fn vis_fields_system(
+    mut terrain_query: Query<(
+        &Terrain,
+        &Surface,
+        &mut MeshMaterial3d<StandardMaterial>,
+        &Mesh3d,
+    )>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut field_vis_state: ResMut<FieldVisState>,
+    registry: Res<FieldVisRegistry>,
+    terrain_assets: Res<TerrainAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if key_input.just_pressed(KeyCode::F1) {
+        field_vis_state.active = None;
+    }
+
+    let (terrain, surface, mut mat3d, mesh3d) = terrain_query.single_mut().unwrap();
+
+    // update material
+    if field_vis_state.shown != field_vis_state.active {
+        let was_shown = field_vis_state.shown.is_some();
+        field_vis_state.shown = field_vis_state.active;
+        field_vis_state.drawn_settings = None;
+        if field_vis_state.active.is_none() {
+            mat3d.0 = terrain_assets.ground_material.clone();
+            if let Some(mesh) = meshes.get_mut(&mesh3d.0) {
+                reset_terrain_color(mesh);
+            }
+        } else if !was_shown {
+            mat3d.0 = terrain_assets.field_vis_material.clone();
+            // vertex colors are multiplied with the texture
+            if let Some(mesh) = meshes.get_mut(&mesh3d.0) {
+                set_terrain_uniform_color(mesh, LinearRgba::WHITE);
+            }
+        }
+    }
+
+    let Some(vis_field) = field_vis_state.active_field(&registry) else {
+        return;
+    };
+
+    let field_dirty = match &vis_field.kind {
+        VisFieldKind::Scalar { field, .. } => field(terrain, surface).dirty_region(),
+        VisFieldKind::Categorical { field, .. } => field(terrain, surface).dirty_region(),
+    };
+    if let Some(dirty) = field_dirty {
+        field_vis_state.pending_region = Some(
+            field_vis_state
+                .pending_region
+                .map_or(dirty, |pending| pending.union(dirty)),
+        );
+    }
+    let is_due = match field_vis_state.update_policy {
+        UpdatePolicy::EveryFrame => true,
+        UpdatePolicy::FixedTicks(ticks) => field_vis_state.ticks_since_update >= ticks,
+        UpdatePolicy::DirtyRegion => field_vis_state.pending_region.is_some(),
+    };
+
+    if let VisFieldKind::Scalar { field, .. } = &vis_field.kind
+        && field_vis_state.auto_range
+        && (is_due
+            || field_vis_state
+                .drawn_settings
+                .is_none_or(|drawn| drawn.color_scheme != field_vis_state.color_scheme))
+    {
+        let (min, max) = field(terrain, surface).compute_min_max();
+        // diverging maps have their neutral color at 0
+        field_vis_state.range = if field_vis_state.color_scheme.is_diverging() {
+            let abs_max = min.abs().max(max.abs());
+            (-abs_max, abs_max)
+        } else {
+            (min, max)
+        };
+    }
+
+    let settings = DisplaySettings {
+        field: field_vis_state.active.unwrap(),
+        range: field_vis_state.range,
+        color_scheme: field_vis_state.color_scheme,
+        log_scale: field_vis_state.log_scale,
+    };
+    let is_outdated = field_vis_state.drawn_settings != Some(settings);
+    if !is_due && !is_outdated {
+        return;
+    }
+    let region = match field_vis_state.update_policy {
+        UpdatePolicy::DirtyRegion if !is_outdated => field_vis_state.pending_region,
+        _ => None,
+    };
+
+    // update the image which is shown on the terrain
+    let Some(image) = images.get_mut(&terrain_assets.field_vis_image) else {
+        return;
+    };
+    match &vis_field.kind {
+        VisFieldKind::Scalar { field, .. } => {
+            let cmap = field_vis_state.color_map();
+            set_image_from_field(image, field(terrain, surface), &cmap, region);
+        }
+        VisFieldKind::Categorical { field, categories } => {
+            set_image_from_categories(
+                image,
+                field(terrain, surface),
+                |id| {
+                    categories
+                        .get(id as usize)
+                        .map_or(LinearRgba::BLACK, |(_, color)| *color)
+                },
+                region,
+            );
+        }
+    }
+    field_vis_state.drawn_settings = Some(settings);
+    field_vis_state.pending_region = None;
+    field_vis_state.ticks_since_update = 0;
+}
+
+pub fn count_ticks_system(mut field_vis_state: ResMut<FieldVisState>) {
+    field_vis_state.ticks_since_update += 1;
+}
+
+fn to_egui_color(color: LinearRgba) -> egui::Color32 {
+    let [r, g, b, _] = Color::from(color).to_srgba().to_u8_array();
+    egui::Color32::from_rgb(r, g, b)
+}
+
+pub fn field_vis_ui_system(
+    mut contexts: EguiContexts,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut field_vis_state: ResMut<FieldVisState>,
+    registry: Res<FieldVisRegistry>,
+) -> Result {
+    if key_input.just_pressed(KeyCode::F2) {
+        field_vis_state.is_panel_visible = !field_vis_state.is_panel_visible;
+    }
+
+    if !field_vis_state.is_panel_visible {
+        return Ok(());
+    }
+
+    let state = &mut *field_vis_state;
+    egui::Window::new("Field overlay")
+        .resizable(false)
+        .anchor(Align2::LEFT_BOTTOM, egui::vec2(5.0, -40.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            let mut active = state.active;
+            let selected_name = state.active_field(&registry).map_or("none", |f| f.name);
+            egui::ComboBox::from_label("field")
+                .selected_text(selected_name)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut active, None, "none");
+                    for (idx, field) in registry.iter().enumerate() {
+                        ui.selectable_value(&mut active, Some(idx), field.name);
+                    }
+                });
+            if active != state.active {
+                state.select(&registry, active);
+            }
+
+            if let Some(VisFieldKind::Scalar { .. }) =
+                state.active_field(&registry).map(|f| &f.kind)
+            {
+                ui.checkbox(&mut state.auto_range, "auto range");
+                ui.add_enabled_ui(!state.auto_range, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("range");
+                        ui.add(egui::DragValue::new(&mut state.range.0).speed(0.01));
+                        ui.add(egui::DragValue::new(&mut state.range.1).speed(0.01));
+                    });
+                });
+                egui::ComboBox::from_label("color map")
+                    .selected_text(state.color_scheme.name())
+                    .show_ui(ui, |ui| {
+                        for scheme in ColorScheme::ALL {
+                            ui.selectable_value(&mut state.color_scheme, scheme, scheme.name());
+                        }
+                    });
+                ui.checkbox(&mut state.log_scale, "log scale");
+                if state.color_scheme == ColorScheme::Custom {
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut state.custom_path);
+                        if ui.button("load").clicked() {
+                            match color_map::load_color_map(Path::new(&state.custom_path)) {
+                                Ok(colors) => {
+                                    state.custom_colors = Some(colors);
+                                    state.load_error = None;
+                                    state.drawn_settings = None;
+                                }
+                                Err(err) => state.load_error = Some(err.to_string()),
+                            }
+                        }
+                    });
+                    if let Some(err) = &state.load_error {
+                        ui.colored_label(egui::Color32::RED, err);
+                    }
+                }
+            }
+
+            if state.active.is_some() {
+                egui::ComboBox::from_label("update")
+                    .selected_text(state.update_policy.name())
+                    .show_ui(ui, |ui| {
+                        for policy in [
+                            UpdatePolicy::EveryFrame,
+                            UpdatePolicy::FixedTicks(DEFAULT_UPDATE_TICKS),
+                            UpdatePolicy::DirtyRegion,
+                        ] {
+                            let is_selected = std::mem::discriminant(&state.update_policy)
+                                == std::mem::discriminant(&policy);
+                            if ui.selectable_label(is_selected, policy.name()).clicked()
+                                && !is_selected
+                            {
+                                state.update_policy = policy;
+                            }
+                        }
+                    });
+                if let UpdatePolicy::FixedTicks(ticks) = &mut state.update_policy {
+                    ui.horizontal(|ui| {
+                        ui.label("ticks");
+                        ui.add(egui::DragValue::new(ticks).range(1..=600));
+                    });
+                }
+            }
+        });
+
+    Ok(())
+}
+
+pub fn cursor_probe_system(
+    mut terrain_ray_cast: TerrainRayCast,
+    mut field_vis_state: ResMut<FieldVisState>,
+) {
+    if field_vis_state.active.is_none() {
+        return;
+    }
+    field_vis_state.cursor_pos = terrain_ray_cast.cursor_hit().map(|p| p.xz());
+}
+
+const COLOR_BAR_SIZE: egui::Vec2 = egui::vec2(240.0, 16.0);
+const COLOR_BAR_SEGMENTS: usize = 64;
+const NUM_TICKS: usize = 5;
+
+fn color_bar_ui(ui: &mut egui::Ui, cmap: &ColorMap) {
+    let (rect, _) = ui.allocate_exact_size(COLOR_BAR_SIZE, egui::Sense::hover());
+    let painter = ui.painter();
+    let segment_width = rect.width() / COLOR_BAR_SEGMENTS as f32;
+    for i in 0..COLOR_BAR_SEGMENTS {
+        let t = (i as f32 + 0.5) / COLOR_BAR_SEGMENTS as f32;
+        let x = rect.left() + i as f32 * segment_width;
+        let segment = egui::Rect::from_min_max(
+            egui::pos2(x, rect.top()),
+            // overlap to avoid gaps between the segments
+            egui::pos2(x + segment_width + 0.5, rect.bottom()),
+        );
+        painter.rect_filled(
+            segment,
+            0.0,
+            to_egui_color(cmap.get_color(cmap.value_at(t))),
+        );
+    }
+
+    // ticks
+    let (tick_rect, _) =
+        ui.allocate_exact_size(egui::vec2(rect.width(), 14.0), egui::Sense::hover());
+    let text_color = ui.visuals().text_color();
+    for i in 0..NUM_TICKS {
+        let t = i as f32 / (NUM_TICKS - 1) as f32;
+        let x = rect.left() + t * rect.width();
+        let align = match i {
+            0 => Align2::LEFT_TOP,
+            i if i == NUM_TICKS - 1 => Align2::RIGHT_TOP,
+            _ => Align2::CENTER_TOP,
+        };
+        ui.painter().line_segment(
+            [
+                egui::pos2(x, rect.bottom()),
+                egui::pos2(x, tick_rect.top() + 3.0),
+            ],
+            egui::Stroke::new(1.0, text_color),
+        );
+        ui.painter().text(
+            egui::pos2(x, tick_rect.top() + 2.0),
+            align,
+            format!("{:.3}", cmap.value_at(t)),
+            egui::FontId::proportional(11.0),
+            text_color,
+        );
+    }
+}
+
+// Legend of the active overlay and the value under the cursor.
+pub fn field_legend_ui_system(
+    mut contexts: EguiContexts,
+    field_vis_state: Res<FieldVisState>,
+    registry: Res<FieldVisRegistry>,
+    field_query: Query<(&Terrain, &Surface)>,
+) -> Result {
+    let Some(vis_field) = field_vis_state.active_field(&registry) else {
+        return Ok(());
+    };
+    let Ok((terrain, surface)) = field_query.single() else {
+        return Ok(());
+    };
+
+    egui::Window::new("Legend")
+        .resizable(false)
+        .anchor(Align2::RIGHT_BOTTOM, egui::vec2(-5.0, -40.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            ui.label(vis_field.name);
+            let cursor_value = match &vis_field.kind {
+                VisFieldKind::Scalar { field, .. } => {
+                    color_bar_ui(ui, &field_vis_state.color_map());
+                    field_vis_state
+                        .cursor_pos
+                        .map(|p| format!("{:.3}", field(terrain, surface).get_bilinear(p)))
+                }
+                VisFieldKind::Categorical { field, categories } => {
+                    for (name, color) in categories {
+                        ui.horizontal(|ui| {
+                            let (rect, _) = ui
+                                .allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+                            ui.painter().rect_filled(rect, 2.0, to_egui_color(*color));
+                            ui.label(*name);
+                        });
+                    }
+                    field_vis_state.cursor_pos.map(|p| {
+                        let id = field(terrain, surface).get_nearest(p) as usize;
+                        categories
+                            .get(id)
+                            .map_or("unknown", |(name, _)| *name)
+                            .to_string()
+                    })
+                }
+            };
+            ui.separator();
+            ui.label(format!(
+                "cursor: {}",
+                cursor_value.as_deref().unwrap_or("-")
+            ));
+        });
+
+    Ok(())
+}
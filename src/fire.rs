@@ -0,0 +1,193 @@
+use bevy::math::{USizeVec2, usizevec2};
+use bevy::prelude::*;
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+use rand::prelude::*;
+
+use crate::organism::{self, Organism};
+use crate::terrain::{Surface, Terrain};
+use crate::weather::Weather;
+use crate::{domain, parameters};
+
+#[derive(Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct FireParameters {
+    // [1/s] ignition rate of a neighbour cell under ideal conditions
+    pub spread_rate: f32,
+    pub burn_duration: f32, // [s]
+    // no spread above this relative soil moisture
+    pub extinction_moisture: f32,
+    // exponential increase of the spread rate per unit of slope towards the neighbour
+    pub slope_factor: f32,
+    pub wind_direction: f32, // [deg], direction the wind is blowing towards, 0 is east
+    pub wind_speed: f32,     // [m/s]
+    // exponential increase of the spread rate per m/s of wind towards the neighbour
+    pub wind_factor: f32,
+    pub lightning_rate: f32, // [1/day] strikes during storms
+    // nutrients left as ash per unit of burned fuel
+    pub ash_nutrients: f32,
+    pub nutrient_decay: f32, // [1/day]
+}
+
+impl Default for FireParameters {
+    fn default() -> Self {
+        FireParameters {
+            spread_rate: 2.0,
+            burn_duration: 3.0,
+            extinction_moisture: 0.35,
+            slope_factor: 2.0,
+            wind_direction: 0.0,
+            wind_speed: 2.0,
+            wind_factor: 0.3,
+            lightning_rate: 0.5,
+            ash_nutrients: 1.0,
+            nutrient_decay: 0.2,
+        }
+    }
+}
+
+// Cells which are currently burning.
+#[derive(Resource, Default)]
+pub struct FireState {
+    burning: Vec<USizeVec2>,
+}
+
+impl FireState {
+    // Cells of a restored surface which are still burning.
+    pub fn from_surface(surface: &Surface) -> Self {
+        let size = surface.burn_time.size;
+        let mut burning = Vec::new();
+        for y in 0..size.y {
+            for x in 0..size.x {
+                if surface.burn_time[[x, y]] > 0.0 {
+                    burning.push(usizevec2(x, y));
+                }
+            }
+        }
+        FireState { burning }
+    }
+
+    pub fn num_burning(&self) -> usize {
+        self.burning.len()
+    }
+
+    // Set the cell at idx on fire if there is fuel and it is not already burning.
+    fn ignite_cell(&mut self, surface: &mut Surface, idx: USizeVec2, params: &FireParameters) {
+        let idx = [idx.x, idx.y];
+        let fuel = surface.veg_density[idx].clamp(0.0, 1.0);
+        if surface.burn_time[idx] > 0.0 || fuel <= 0.0 {
+            return;
+        }
+        surface.burn_time[idx] = params.burn_duration;
+        surface.nutrients[idx] += params.ash_nutrients * fuel;
+        self.burning.push(idx.into());
+    }
+
+    pub fn ignite(&mut self, surface: &mut Surface, pos: Vec2, params: &FireParameters) {
+        let idx = surface.burn_time.nearest_index(pos);
+        self.ignite_cell(surface, idx, params);
+    }
+}
+
+const NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(-1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(1, 0),
+    IVec2::new(-1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(1, 1),
+];
+
+pub fn fire_system(
+    mut commands: Commands,
+    mut fire: ResMut<FireState>,
+    mut field_query: Query<(&Terrain, &mut Surface)>,
+    organism_query: Query<(Entity, &Transform, &Organism)>,
+    weather: Res<Weather>,
+    time: Res<Time>,
+    general_params: Res<parameters::GeneralParameters>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+) {
+    let (terrain, mut surface) = field_query.single_mut().unwrap();
+    let params = &general_params.fire;
+    let dt = time.delta_secs();
+
+    // lightning
+    let strike_prob = params.lightning_rate * dt / general_params.sun.day_duration;
+    if weather.is_storm && rng.random::<f32>() < strike_prob {
+        let pos = vec2(rng.random::<f32>(), rng.random::<f32>()) * domain::size_f32();
+        fire.ignite(&mut surface, pos, params);
+    }
+
+    // nutrients are washed out over time
+    let decay = (params.nutrient_decay * dt / general_params.sun.day_duration).min(1.0);
+    for nutrients in surface.nutrients.iter_mut() {
+        *nutrients *= 1.0 - decay;
+    }
+
+    if fire.burning.is_empty() {
+        return;
+    }
+
+    // spread to neighbours
+    let size = surface.burn_time.size.as_ivec2();
+    let cell_size = 1.0 / surface.burn_time.idx_scale;
+    let wind_dir = Vec2::from_angle(params.wind_direction.to_radians());
+    let mut ignited = Vec::new();
+    for cell in &fire.burning {
+        let pos = cell.as_vec2() * cell_size;
+        let height = terrain.height_map.get_bilinear(pos);
+        for offset in NEIGHBOURS {
+            let neighbour = cell.as_ivec2() + offset;
+            if neighbour.cmplt(IVec2::ZERO).any() || neighbour.cmpge(size).any() {
+                continue;
+            }
+            let idx = [neighbour.x as usize, neighbour.y as usize];
+            if surface.burn_time[idx] > 0.0 {
+                continue;
+            }
+
+            let fuel = surface.veg_density[idx].clamp(0.0, 1.0);
+            let dryness = (1.0 - surface.moisture[idx] / params.extinction_moisture).max(0.0);
+            let neighbour_pos = neighbour.as_vec2() * cell_size;
+            let dist = (neighbour_pos - pos).length();
+            let slope = (terrain.height_map.get_bilinear(neighbour_pos) - height) / dist;
+            let wind = params.wind_speed * wind_dir.dot(offset.as_vec2().normalize());
+            let rate = params.spread_rate
+                * fuel
+                * dryness
+                * (params.slope_factor * slope).exp()
+                * (params.wind_factor * wind).exp();
+            if rng.random::<f32>() < rate * dt {
+                ignited.push(neighbour.as_usizevec2());
+            }
+        }
+    }
+
+    // burn down
+    fire.burning.retain(|cell| {
+        let idx = [cell.x, cell.y];
+        surface.burn_time[idx] = (surface.burn_time[idx] - dt).max(0.0);
+        surface.burn_time[idx] > 0.0
+    });
+
+    for cell in ignited {
+        fire.ignite_cell(&mut surface, cell, params);
+    }
+
+    // organisms in burning cells die
+    for (id, transform, organism) in organism_query.iter() {
+        if surface.burn_time.get_nearest(transform.translation.xz()) > 0.0 {
+            organism::remove_organism(
+                &mut commands,
+                &mut surface,
+                id,
+                transform,
+                organism,
+                organism::DeathCause::Fire,
+            );
+        }
+    }
+}
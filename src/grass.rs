@@ -7,13 +7,28 @@ use bevy::{
     shader::ShaderRef,
 };
 
-#[derive(Resource, egui_probe::EguiProbe)]
+use crate::habitat;
+
+#[derive(Resource, Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct GrassParameters {
     pub max_age: f32,
     pub spawn_radius: f32,
     pub orientation_max_angle: f32,
     pub below_surface_depth: f32,
     pub surface_area: f32,
+    // growth stops below the wilting point and is unconstrained above the optimal moisture
+    pub wilting_point: f32,
+    pub optimal_moisture: f32,
+    pub drought_tolerance: f32, // [s] of full water stress until death
+    // relative growth increase per unit of nutrients
+    pub nutrient_boost: f32,
+    // temperature tolerance range [°C], growth is only unconstrained in the optimal range
+    pub min_temperature: f32,
+    pub min_optimal_temperature: f32,
+    pub max_optimal_temperature: f32,
+    pub max_temperature: f32,
+    pub habitat_preferences: habitat::HabitatPreferences,
 }
 
 impl Default for GrassParameters {
@@ -24,10 +39,42 @@ impl Default for GrassParameters {
             orientation_max_angle: 0.25,
             below_surface_depth: 0.08,
             surface_area: 0.25,
+            wilting_point: 0.15,
+            optimal_moisture: 0.4,
+            drought_tolerance: 240.0,
+            nutrient_boost: 1.0,
+            min_temperature: 0.0,
+            min_optimal_temperature: 10.0,
+            max_optimal_temperature: 25.0,
+            max_temperature: 38.0,
+            habitat_preferences: habitat::HabitatPreferences {
+                wetland: 0.3,
+                meadow: 1.0,
+                dry_slope: 0.6,
+                rock: 0.05,
+                alpine: 0.3,
+            },
         }
     }
 }
 
+impl GrassParameters {
+    // Growth factor in [0,1] due to the available water.
+    pub fn water_factor(&self, moisture: f32) -> f32 {
+        ((moisture - self.wilting_point) / (self.optimal_moisture - self.wilting_point))
+            .clamp(0.0, 1.0)
+    }
+
+    // Growth factor in [0,1] due to the temperature.
+    pub fn temperature_factor(&self, temperature: f32) -> f32 {
+        let cold = (temperature - self.min_temperature)
+            / (self.min_optimal_temperature - self.min_temperature);
+        let hot = (self.max_temperature - temperature)
+            / (self.max_temperature - self.max_optimal_temperature);
+        cold.min(hot).clamp(0.0, 1.0)
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct GrassAssets {
     pub mesh: Handle<Mesh>,
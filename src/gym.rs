@@ -0,0 +1,346 @@
+//! Gym-style interface for reinforcement learning on top of the headless app.
+//! An episode starts with [`Environment::reset`] and advances with [`Environment::step`], which
+//! applies an action, simulates a fixed number of steps and returns the new observation, the
+//! reward and whether the episode is done.
+//!
+//! ```ignore
+//! let mut env = Environment::new(EnvironmentConfig::default(), PopulationGrowth)?;
+//! let mut observation = env.reset(42)?;
+//! loop {
+//!     let action = agent.act(&observation);
+//!     let (next, reward, is_done) = env.step(action);
+//!     agent.learn(reward);
+//!     if is_done {
+//!         break;
+//!     }
+//!     observation = next;
+//! }
+//! ```
+
+use bevy::ecs::system::RunSystemOnce;
+use bevy::math::{USizeVec2, usizevec2};
+use bevy::prelude::*;
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+
+use crate::cli::{self, Options};
+use crate::domain::{self, Field};
+use crate::field_vis::{FieldVisRegistry, VisFieldKind};
+use crate::fire::FireState;
+use crate::organism::{self, Organism};
+use crate::parameters::GeneralParameters;
+use crate::replay::Intervention;
+use crate::terrain::{Surface, Terrain};
+
+// An intervention of the agent at a position in domain coordinates, like a right click with
+// the plant tool.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Wait,
+    Plant(Vec2),
+    Burn(Vec2),
+}
+
+#[derive(Clone, Debug)]
+pub struct Observation {
+    // simulated time since the reset [s]
+    pub time: f64,
+    pub population: usize,
+    // side length of the downsampled fields
+    pub resolution: usize,
+    // fields in the order of EnvironmentConfig::fields, row by row
+    pub fields: Vec<Vec<f32>>,
+}
+
+// Reward of a step from the observations before and after it.
+pub trait Reward {
+    fn reward(&mut self, previous: &Observation, current: &Observation) -> f32;
+}
+
+impl<F: FnMut(&Observation, &Observation) -> f32> Reward for F {
+    fn reward(&mut self, previous: &Observation, current: &Observation) -> f32 {
+        self(previous, current)
+    }
+}
+
+// Change of the number of organisms.
+pub struct PopulationGrowth;
+
+impl Reward for PopulationGrowth {
+    fn reward(&mut self, previous: &Observation, current: &Observation) -> f32 {
+        current.population as f32 - previous.population as f32
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EnvironmentConfig {
+    // world size, parameters, scenario etc. The seed is set with each reset.
+    pub options: Options,
+    // names of the visualized fields which are observed
+    pub fields: Vec<String>,
+    // side length of the observed fields, at most the world size
+    pub resolution: usize,
+    // fixed simulation steps between two actions
+    pub steps_per_action: u32,
+    // number of actions after which an episode is done
+    pub max_actions: u32,
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        EnvironmentConfig {
+            options: Options::default(),
+            fields: vec!["vegetation density".to_string(), "moisture".to_string()],
+            resolution: 16,
+            steps_per_action: 60,
+            max_actions: 1000,
+        }
+    }
+}
+
+pub struct Environment {
+    config: EnvironmentConfig,
+    reward: Box<dyn Reward>,
+    app: Option<App>,
+    observation: Option<Observation>,
+    num_actions: u32,
+}
+
+impl Environment {
+    // Fails if one of the observed fields does not exist.
+    pub fn new(config: EnvironmentConfig, reward: impl Reward + 'static) -> Result<Self, String> {
+        let vis_fields = crate::vis_fields();
+        if let Some(name) = config
+            .fields
+            .iter()
+            .find(|name| !vis_fields.iter().any(|vis_field| vis_field.name == *name))
+        {
+            return Err(format!("unknown field {name}"));
+        }
+        if config.resolution == 0 {
+            return Err("the resolution has to be positive".to_string());
+        }
+
+        Ok(Environment {
+            config,
+            reward: Box::new(reward),
+            app: None,
+            observation: None,
+            num_actions: 0,
+        })
+    }
+
+    // Start a new episode with a freshly generated world. Fails if one of the files given in
+    // the options can not be loaded.
+    pub fn reset(&mut self, seed: u64) -> Result<Observation, String> {
+        let options = Options {
+            seed: Some(seed),
+            duration: None,
+            is_headless: true,
+            ..self.config.options.clone()
+        };
+        let mut app = crate::build_app(options)?;
+        app.finish();
+        app.cleanup();
+        // runs the startup systems
+        app.update();
+
+        let observation = observe(app.world_mut(), &self.config);
+        self.app = Some(app);
+        self.observation = Some(observation.clone());
+        self.num_actions = 0;
+        Ok(observation)
+    }
+
+    // Apply the action and advance the simulation. Panics if no episode was started.
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool) {
+        let app = self
+            .app
+            .as_mut()
+            .expect("reset has to be called before the first step");
+        app.world_mut()
+            .run_system_once_with(apply_action_system, action)
+            .unwrap();
+        for _ in 0..self.config.steps_per_action {
+            app.update();
+        }
+        self.num_actions += 1;
+
+        let observation = observe(app.world_mut(), &self.config);
+        let previous = self.observation.replace(observation.clone()).unwrap();
+        let reward = self.reward.reward(&previous, &observation);
+        let is_done = self.num_actions >= self.config.max_actions
+            || app.world().resource::<cli::StopRequest>().is_requested;
+        (observation, reward, is_done)
+    }
+}
+
+fn apply_action_system(
+    In(action): In<Action>,
+    mut commands: Commands,
+    mut field_query: Query<(&Terrain, &mut Surface)>,
+    mut fire_state: ResMut<FireState>,
+    grass_assets: Res<crate::GrassAssets>,
+    general_params: Res<GeneralParameters>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+) {
+    let (terrain, mut surface) = field_query.single_mut().unwrap();
+    match action {
+        Action::Wait => {}
+        Action::Plant(p) if domain::bounds().contains(p) => {
+            organism::spawn_seedling(
+                &mut commands,
+                &mut organism_ids,
+                &grass_assets,
+                terrain,
+                p,
+                None,
+                &general_params.grass,
+                &mut rng,
+            );
+            commands.write_message(Intervention::Plant {
+                position: p.to_array(),
+                count: 1,
+            });
+        }
+        Action::Burn(p) if domain::bounds().contains(p) => {
+            fire_state.ignite(&mut surface, p, &general_params.fire);
+            commands.write_message(Intervention::Ignite {
+                position: p.to_array(),
+            });
+        }
+        // actions outside of the domain have no effect
+        _ => {}
+    }
+}
+
+// Field cells min..max which are combined into the observed cell idx. The blocks cover the
+// whole field even if the resolution does not divide its size, and have at least one cell.
+fn block_bounds<T>(field: &Field<T>, resolution: usize, idx: USizeVec2) -> (USizeVec2, USizeVec2) {
+    let min = (idx * field.size / resolution).min(field.size - USizeVec2::ONE);
+    let max = ((idx + USizeVec2::ONE) * field.size / resolution).max(min + USizeVec2::ONE);
+    (min, max)
+}
+
+// Mean of each block of the field on a grid with the given side length.
+fn downsample_mean(field: &Field<f32>, resolution: usize) -> Vec<f32> {
+    let mut values = Vec::with_capacity(resolution * resolution);
+    for y in 0..resolution {
+        for x in 0..resolution {
+            let (min, max) = block_bounds(field, resolution, usizevec2(x, y));
+            // in double precision, large blocks accumulate rounding errors otherwise
+            let mut sum = 0.0;
+            for by in min.y..max.y {
+                for bx in min.x..max.x {
+                    sum += field[[bx, by]] as f64;
+                }
+            }
+            values.push((sum / (max - min).element_product() as f64) as f32);
+        }
+    }
+    values
+}
+
+// Value at the center of each block, for categories which can not be averaged.
+fn downsample_nearest(field: &Field<u8>, resolution: usize) -> Vec<f32> {
+    let mut values = Vec::with_capacity(resolution * resolution);
+    for y in 0..resolution {
+        for x in 0..resolution {
+            let (min, max) = block_bounds(field, resolution, usizevec2(x, y));
+            let center = (min + max) / 2;
+            values.push(field[center.to_array()] as f32);
+        }
+    }
+    values
+}
+
+fn observe(world: &mut World, config: &EnvironmentConfig) -> Observation {
+    let population = world
+        .query_filtered::<(), With<Organism>>()
+        .iter(world)
+        .count();
+    let (terrain, surface) = world.query::<(&Terrain, &Surface)>().single(world).unwrap();
+    let registry = world.resource::<FieldVisRegistry>();
+    let fields = config
+        .fields
+        .iter()
+        .map(|name| {
+            let vis_field = registry
+                .iter()
+                .find(|vis_field| vis_field.name == name)
+                .expect("the fields are checked in Environment::new");
+            match &vis_field.kind {
+                VisFieldKind::Scalar { field, .. } => {
+                    downsample_mean(field(terrain, surface), config.resolution)
+                }
+                VisFieldKind::Categorical { field, .. } => {
+                    downsample_nearest(field(terrain, surface), config.resolution)
+                }
+            }
+        })
+        .collect();
+
+    Observation {
+        time: world.resource::<Time<Fixed>>().elapsed_secs_f64(),
+        population,
+        resolution: config.resolution,
+        fields,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fields of 512 and 64 cells per side with the default domain size of the unit tests
+    fn fields() -> Vec<Field<f32>> {
+        vec![Field::new(3), Field::new(0)]
+    }
+
+    #[test]
+    fn blocks_cover_each_cell_once() {
+        for field in fields() {
+            for resolution in [1, 3, 7, 64] {
+                let mut counts = vec![0; field.num_elem()];
+                for y in 0..resolution {
+                    for x in 0..resolution {
+                        let (min, max) = block_bounds(&field, resolution, usizevec2(x, y));
+                        for by in min.y..max.y {
+                            for bx in min.x..max.x {
+                                counts[by * field.size.x + bx] += 1;
+                            }
+                        }
+                    }
+                }
+                assert!(
+                    counts.iter().all(|&count| count == 1),
+                    "{} -> {resolution}",
+                    field.size.x
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mean_of_a_constant_field_is_preserved() {
+        for mut field in fields() {
+            field.fill(0.3);
+            for resolution in [3, 7] {
+                let values = downsample_mean(&field, resolution);
+                assert_eq!(values.len(), resolution * resolution);
+                assert!(values.iter().all(|value| (value - 0.3).abs() < 1e-5));
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_of_a_constant_field_is_preserved() {
+        let mut field = Field::<u8>::new(3);
+        field.fill(4);
+        for resolution in [3, 7] {
+            let values = downsample_nearest(&field, resolution);
+            assert_eq!(values, vec![4.0; resolution * resolution]);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::color::LinearRgba;
+use bevy::math::usizevec2;
+use bevy::prelude::*;
+
+use crate::parameters;
+use crate::terrain::{Surface, Terrain};
+
+// simulated time between two classifications [s]
+const UPDATE_INTERVAL: f32 = 5.0;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Habitat {
+    Wetland,
+    Meadow,
+    DrySlope,
+    Rock,
+    Alpine,
+}
+
+impl Habitat {
+    pub const ALL: [Habitat; 5] = [
+        Habitat::Wetland,
+        Habitat::Meadow,
+        Habitat::DrySlope,
+        Habitat::Rock,
+        Habitat::Alpine,
+    ];
+
+    pub fn from_id(id: u8) -> Habitat {
+        Habitat::ALL[(id as usize).min(Habitat::ALL.len() - 1)]
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Habitat::Wetland => "wetland",
+            Habitat::Meadow => "meadow",
+            Habitat::DrySlope => "dry slope",
+            Habitat::Rock => "rock",
+            Habitat::Alpine => "alpine",
+        }
+    }
+
+    pub fn color(self) -> LinearRgba {
+        match self {
+            Habitat::Wetland => LinearRgba::rgb(0.1, 0.3, 0.6),
+            Habitat::Meadow => LinearRgba::rgb(0.3, 0.6, 0.15),
+            Habitat::DrySlope => LinearRgba::rgb(0.75, 0.6, 0.25),
+            Habitat::Rock => LinearRgba::rgb(0.35, 0.35, 0.35),
+            Habitat::Alpine => LinearRgba::rgb(0.85, 0.9, 0.95),
+        }
+    }
+}
+
+// Thresholds of the classification. The rules are applied in the order
+// rock, alpine, wetland, dry slope and everything else is meadow.
+#[derive(Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HabitatParameters {
+    pub rock_slope: f32, // height change per unit of distance
+    pub alpine_height: f32,
+    pub wetland_height: f32,
+    pub wetland_moisture: f32,
+    pub dry_slope: f32,
+    pub dry_moisture: f32,
+}
+
+impl Default for HabitatParameters {
+    fn default() -> Self {
+        HabitatParameters {
+            rock_slope: 1.2,
+            alpine_height: 1.8,
+            wetland_height: -1.2,
+            wetland_moisture: 0.8,
+            dry_slope: 0.5,
+            dry_moisture: 0.2,
+        }
+    }
+}
+
+impl HabitatParameters {
+    pub fn classify(&self, height: f32, slope: f32, moisture: f32) -> Habitat {
+        if slope > self.rock_slope {
+            Habitat::Rock
+        } else if height > self.alpine_height {
+            Habitat::Alpine
+        } else if height < self.wetland_height || moisture > self.wetland_moisture {
+            Habitat::Wetland
+        } else if slope > self.dry_slope || moisture < self.dry_moisture {
+            Habitat::DrySlope
+        } else {
+            Habitat::Meadow
+        }
+    }
+}
+
+// Suitability of each habitat for a species in [0,1].
+#[derive(Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+pub struct HabitatPreferences {
+    pub wetland: f32,
+    pub meadow: f32,
+    pub dry_slope: f32,
+    pub rock: f32,
+    pub alpine: f32,
+}
+
+impl HabitatPreferences {
+    pub fn suitability(&self, habitat: Habitat) -> f32 {
+        match habitat {
+            Habitat::Wetland => self.wetland,
+            Habitat::Meadow => self.meadow,
+            Habitat::DrySlope => self.dry_slope,
+            Habitat::Rock => self.rock,
+            Habitat::Alpine => self.alpine,
+        }
+    }
+}
+
+pub fn classify_habitat_system(
+    mut field_query: Query<(&Terrain, &mut Surface)>,
+    general_params: Res<parameters::GeneralParameters>,
+    time: Res<Time>,
+    mut next_update: Local<f32>,
+) {
+    if time.elapsed_secs() < *next_update {
+        return;
+    }
+    *next_update = time.elapsed_secs() + UPDATE_INTERVAL;
+
+    let params = &general_params.habitat;
+    let (terrain, mut surface) = field_query.single_mut().unwrap();
+    let surface = &mut *surface;
+    let height_map = &terrain.height_map;
+    // all fields have the same resolution
+    for y in 0..surface.habitat.size.y {
+        for x in 0..surface.habitat.size.x {
+            let slope = height_map.gradient(usizevec2(x, y)).length();
+            let id = params
+                .classify(height_map[[x, y]], slope, surface.moisture[[x, y]])
+                .id();
+            // writing marks the cell dirty, so only changed classes are written
+            if surface.habitat[[x, y]] != id {
+                surface.habitat[[x, y]] = id;
+            }
+        }
+    }
+}
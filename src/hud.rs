@@ -1,9 +1,49 @@
 use bevy::prelude::*;
 
+use crate::calendar;
+use crate::time_control::TimeControl;
+
 #[derive(Component, Default)]
 pub struct GameSpeedLabel;
 
-pub fn hud_system(mut game_speed_query: Query<&mut Text, With<GameSpeedLabel>>, time: Res<Time<Virtual>>) {
-    let mut game_speed_text = game_speed_query.single_mut().unwrap();
-    **game_speed_text = format!("game speed: {}x", time.relative_speed());
+#[derive(Component, Default)]
+pub struct DateLabel;
+
+pub fn hud_system(
+    mut game_speed_query: Query<(&mut Text, &mut TextColor), With<GameSpeedLabel>>,
+    time: Res<Time<Virtual>>,
+    time_control: Res<TimeControl>,
+) {
+    let (mut game_speed_text, mut text_color) = game_speed_query.single_mut().unwrap();
+    if time.is_paused() {
+        **game_speed_text = "game speed: paused".to_string();
+        *text_color = TextColor::WHITE;
+    } else if time_control.is_behind() {
+        **game_speed_text = format!(
+            "game speed: {}x (behind real time, {:.1}x achieved)",
+            time.relative_speed(),
+            time_control.achieved_speed()
+        );
+        *text_color = TextColor(Color::srgb(1.0, 0.3, 0.2));
+    } else {
+        **game_speed_text = format!("game speed: {}x", time.relative_speed());
+        *text_color = TextColor::WHITE;
+    }
 }
+
+pub fn date_label_system(
+    mut date_query: Query<&mut Text, With<DateLabel>>,
+    calendar: Res<calendar::Calendar>,
+) {
+    let mut date_text = date_query.single_mut().unwrap();
+    let minutes = (calendar.time_of_day() * 24.0 * 60.0) as u32;
+    **date_text = format!(
+        "{}, day {}, year {} - {:02}:{:02}",
+        calendar.season(),
+        calendar.day_of_season() + 1,
+        calendar.year() + 1,
+        minutes / 60,
+        minutes % 60
+    );
+}
+
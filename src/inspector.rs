@@ -0,0 +1,223 @@
+//! Inspector for a single organism.
+//! In select mode (toggled with Tab) a right click selects the organism closest to the cursor.
+//! The selection is highlighted in the 3D view and can be killed or cloned from the panel.
+
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::input::{egui_wants_any_keyboard_input, egui_wants_any_pointer_input};
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+
+use crate::domain;
+use crate::organism::{self, Organism};
+use crate::parameters;
+use crate::player_inputs::{TerrainRayCast, ToolMode};
+use crate::replay::Intervention;
+use crate::terrain::{Surface, Terrain};
+use crate::undo;
+
+// maximum distance between the cursor and a selected organism
+const PICK_RADIUS: f32 = 0.5;
+const HIGHLIGHT_COLOR: Color = Color::srgb(1.0, 0.8, 0.0);
+
+#[derive(Resource, Default)]
+pub struct Selection {
+    pub entity: Option<Entity>,
+}
+
+pub struct InspectorPlugin;
+
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_systems(
+                Update,
+                (
+                    select_organism_system
+                        .run_if(resource_equals(ToolMode::Select))
+                        .run_if(
+                            not(egui_wants_any_keyboard_input)
+                                .and(not(egui_wants_any_pointer_input)),
+                        ),
+                    highlight_selection_system,
+                ),
+            )
+            .add_systems(EguiPrimaryContextPass, inspector_ui_system);
+    }
+}
+
+pub fn select_organism_system(
+    mut terrain_ray_cast: TerrainRayCast,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut selection: ResMut<Selection>,
+    organism_query: Query<(Entity, &Transform), With<Organism>>,
+) {
+    if !mouse_button_input.just_released(MouseButton::Right) {
+        return;
+    }
+
+    let Some(hit_point) = terrain_ray_cast.cursor_hit() else {
+        return;
+    };
+
+    selection.entity = organism_query
+        .iter()
+        .map(|(id, transform)| (id, transform.translation.xz().distance(hit_point.xz())))
+        .filter(|(_, distance)| *distance < PICK_RADIUS)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(id, _)| id);
+}
+
+pub fn highlight_selection_system(
+    selection: Res<Selection>,
+    organism_query: Query<(&Transform, &Organism)>,
+    mut gizmos: Gizmos,
+) {
+    let Some((transform, organism)) = selection.entity.and_then(|id| organism_query.get(id).ok())
+    else {
+        return;
+    };
+
+    let base = transform.translation;
+    let radius = organism.surface_area().max(PICK_RADIUS * 0.5);
+    // circles are in the xy-plane by default
+    let rotation = Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+    gizmos.circle(
+        Isometry3d::new(base + Vec3::Y * 0.1, rotation),
+        radius,
+        HIGHLIGHT_COLOR,
+    );
+    gizmos.line(
+        base,
+        base + Vec3::Y * (1.0 + transform.scale.y),
+        HIGHLIGHT_COLOR,
+    );
+}
+
+pub fn inspector_ui_system(
+    mut contexts: EguiContexts,
+    mut commands: Commands,
+    tool_mode: Res<ToolMode>,
+    mut selection: ResMut<Selection>,
+    organism_query: Query<(&Transform, &Organism)>,
+    mut field_query: Query<(&Terrain, &mut Surface)>,
+    grass_assets: Res<crate::GrassAssets>,
+    general_params: Res<parameters::GeneralParameters>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+    mut undo_history: ResMut<undo::UndoHistory>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+) -> Result {
+    // the organism may have died
+    let selected = selection
+        .entity
+        .and_then(|id| organism_query.get(id).ok().map(|o| (id, o)));
+    if selected.is_none() {
+        selection.entity = None;
+        if *tool_mode != ToolMode::Select {
+            return Ok(());
+        }
+    }
+
+    let Ok((terrain, mut surface)) = field_query.single_mut() else {
+        return Ok(());
+    };
+
+    egui::Window::new("Inspector")
+        .resizable(false)
+        .anchor(Align2::RIGHT_CENTER, egui::vec2(-5.0, 0.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            let Some((id, (transform, organism))) = selected else {
+                ui.label("right click an organism to select it");
+                return;
+            };
+
+            let pos = transform.translation;
+            egui::Grid::new("organism").num_columns(2).show(ui, |ui| {
+                ui.label("entity");
+                ui.label(format!("{id}"));
+                ui.end_row();
+                let lineage = organism.lineage();
+                ui.label("lineage id");
+                ui.label(format!("{}", organism.id().0));
+                ui.end_row();
+                ui.label("parent");
+                ui.label(match lineage.parent {
+                    Some(parent) => format!("{}", parent.0),
+                    None => "none".to_string(),
+                });
+                ui.end_row();
+                ui.label("generation");
+                ui.label(format!("{}", lineage.generation));
+                ui.end_row();
+                ui.label("founder");
+                ui.label(format!("{}", organism.founder().0));
+                ui.end_row();
+                ui.label("age");
+                ui.label(format!("{:.1} s", organism.age()));
+                ui.end_row();
+                ui.label("size");
+                ui.label(format!("{:.3}", organism.size()));
+                ui.end_row();
+                ui.label("surface area");
+                ui.label(format!("{:.3}", organism.surface_area()));
+                ui.end_row();
+                ui.label("water deficit");
+                ui.label(format!("{:.1} s", organism.water_deficit()));
+                ui.end_row();
+                ui.label("position");
+                ui.label(format!("({:.2}, {:.2}, {:.2})", pos.x, pos.y, pos.z));
+                ui.end_row();
+                ui.label("scale");
+                ui.label(format!("{:.3}", transform.scale.x));
+                ui.end_row();
+                ui.label("vegetation density");
+                ui.label(format!("{:.3}", surface.veg_density.get_bilinear(pos.xz())));
+                ui.end_row();
+            });
+
+            ui.horizontal(|ui| {
+                if ui.button("kill").clicked() {
+                    organism::remove_organism(
+                        &mut commands,
+                        &mut surface,
+                        id,
+                        transform,
+                        organism,
+                        organism::DeathCause::Removed,
+                    );
+                    commands.write_message(Intervention::Remove {
+                        position: pos.xz().to_array(),
+                        count: 1,
+                    });
+                    undo_history.record_removed(vec![(*transform, organism.clone())]);
+                    selection.entity = None;
+                }
+                if ui.button("clone").clicked() {
+                    // place the copy nearby like a propagated seedling
+                    let area = Circle::new(general_params.grass.spawn_radius);
+                    let bounds = domain::bounds();
+                    let p =
+                        (area.sample_interior(&mut rng) + pos.xz()).clamp(bounds.min, bounds.max);
+                    let height = terrain.height_map.get_bilinear(p)
+                        - general_params.grass.below_surface_depth;
+                    let copy = organism.duplicate(&mut organism_ids);
+                    organism::spawn_copy(
+                        &mut commands,
+                        &mut organism_ids,
+                        &mut surface,
+                        &grass_assets,
+                        transform.with_translation(Vec3::new(p.x, height, p.y)),
+                        &copy,
+                    );
+                    commands.write_message(Intervention::Plant {
+                        position: p.to_array(),
+                        count: 1,
+                    });
+                    undo_history.record_spawned(vec![copy.id()]);
+                }
+            });
+        });
+
+    Ok(())
+}
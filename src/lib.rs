@@ -0,0 +1,415 @@
+use bevy::app::{ScheduleRunnerPlugin, TaskPoolOptions, TaskPoolPlugin};
+use bevy::camera;
+use bevy::dev_tools::fps_overlay::{FpsOverlayConfig, FpsOverlayPlugin, FrameTimeGraphConfig};
+use bevy::light;
+use bevy::pbr;
+use bevy::post_process::bloom::Bloom;
+use bevy::prelude::*;
+use bevy::render::RenderPlugin;
+use bevy::render::settings::WgpuSettings;
+use bevy::time::TimeUpdateStrategy;
+use bevy::window::ExitCondition;
+use bevy::winit::WinitPlugin;
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use bevy_egui::{
+    EguiPlugin, EguiPrimaryContextPass,
+    input::{egui_wants_any_keyboard_input, egui_wants_any_pointer_input},
+};
+
+use crate::camera_controller::*;
+use crate::field_vis::{RegisterVisField, VisField};
+use crate::grass::{GrassAssets, create_grass_material, create_grass_mesh};
+use crate::terrain::*;
+
+mod brush;
+mod calendar;
+mod camera_controller;
+mod cli;
+mod color_map;
+mod contour;
+mod domain;
+mod field_vis;
+mod fire;
+mod grass;
+pub mod gym;
+mod habitat;
+mod hud;
+mod inspector;
+mod lineage;
+mod metrics;
+mod minimap;
+mod organism;
+mod parameters;
+mod player_inputs;
+mod remote;
+mod replay;
+mod scenario;
+mod scripting;
+mod snapshot;
+mod spatial;
+mod sweep;
+mod temperature;
+mod terrain;
+mod time_control;
+mod undo;
+mod weather;
+
+pub use cli::Options;
+pub use sweep::run_sweep;
+
+// The simulation app with all plugins and systems. Headless apps can also be updated manually,
+// which advances the simulation by one fixed step per update.
+// Fails if one of the files given in the options can not be loaded.
+pub fn build_app(mut options: Options) -> Result<App, String> {
+    // a replay regenerates the world of the recorded run, which is then not simulated
+    let replay_log = match &options.replay {
+        Some(path) => Some(
+            replay::EventLog::load(path)
+                .map_err(|err| format!("failed to load event log {}: {err}", path.display()))?,
+        ),
+        None => None,
+    };
+    if let Some(log) = &replay_log {
+        options.seed = log.seed;
+        options.world_size = log.world_size;
+        options.snapshot = None;
+        options.scenario = None;
+        options.script = None;
+        options.duration = None;
+    }
+
+    let general_params = cli::initial_parameters(&options)?;
+    domain::set_size_pow(options.world_size_pow())?;
+    let timestep = Duration::from_secs_f64(1.0 / options.fixed_hz);
+
+    let mut default_plugins = DefaultPlugins.build();
+    if let Some(threads) = options.threads {
+        default_plugins = default_plugins.set(TaskPoolPlugin {
+            task_pool_options: TaskPoolOptions::with_num_threads(threads),
+        });
+    }
+
+    let mut app = App::new();
+    if options.is_headless {
+        // No window and no renderer. Each update advances the simulation by exactly one fixed
+        // step, so runs are as fast as possible and independent of the frame rate.
+        app.add_plugins(
+            default_plugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    ..default()
+                })
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }
+                    .into(),
+                    ..default()
+                })
+                .disable::<WinitPlugin>()
+                // only part of the default plugins if there is no window plugin
+                .add(ScheduleRunnerPlugin::run_loop(Duration::ZERO)),
+        )
+        .insert_resource(TimeUpdateStrategy::ManualDuration(timestep));
+    } else {
+        app.add_plugins(default_plugins).add_plugins(FpsOverlayPlugin {
+            config: FpsOverlayConfig {
+                text_config: TextFont {
+                    font_size: 24.0,
+                    ..Default::default()
+                },
+                // We can also set the refresh interval for the FPS counter
+                refresh_interval: core::time::Duration::from_millis(100),
+                enabled: true,
+                frame_time_graph_config: FrameTimeGraphConfig {
+                    enabled: true,
+                    // The minimum acceptable fps
+                    min_fps: 15.0,
+                    // The target fps
+                    target_fps: 60.0,
+                },
+                ..Default::default()
+            },
+        });
+    }
+
+    if let Some(path) = &options.scenario {
+        let scenario = scenario::Scenario::load(path)
+            .map_err(|err| format!("failed to load scenario {}: {err}", path.display()))?;
+        app.insert_resource(scenario);
+    }
+
+    if let Some(port) = options.remote_port {
+        app.add_plugins(remote::RemoteControlPlugin { port });
+    }
+
+    if let Some(path) = &options.script {
+        let script = scripting::Script::load(path)
+            .map_err(|err| format!("failed to load script {}: {err}", path.display()))?;
+        app.insert_resource(script);
+    }
+
+    if let Some(path) = &options.snapshot {
+        app.insert_resource(snapshot::Snapshot::load_for_run(path)?);
+    }
+
+    match replay_log {
+        Some(log) => app
+            .add_plugins(replay::ReplayPlugin)
+            .insert_resource(replay::Replay::new(log)),
+        None => app
+            .add_plugins(time_control::TimeControlPlugin)
+            .insert_resource(replay::EventLog::new(&options)),
+    };
+
+    let entropy_plugin = match options.seed {
+        Some(seed) => EntropyPlugin::<WyRand>::with_seed(seed.to_le_bytes()),
+        None => EntropyPlugin::<WyRand>::default(),
+    };
+
+    for vis_field in vis_fields() {
+        app.register_vis_field(vis_field);
+    }
+
+    app.insert_resource(GlobalAmbientLight::NONE)
+        .add_plugins(EguiPlugin::default())
+        .add_plugins(CameraControllerPlugin)
+        .add_plugins(field_vis::FieldVisPlugin)
+        .add_plugins(contour::ContourPlugin)
+        .add_plugins(minimap::MinimapPlugin)
+        .add_plugins(inspector::InspectorPlugin)
+        .add_plugins(brush::BrushPlugin)
+        .add_plugins(undo::UndoPlugin)
+        .add_plugins(scenario::ScenarioPlugin)
+        .add_plugins(scripting::ScriptPlugin)
+        .add_plugins(replay::EventLogPlugin)
+        .add_plugins(lineage::LineagePlugin)
+        .add_plugins(spatial::SpatialStatisticsPlugin)
+        .add_message::<organism::OrganismBorn>()
+        .add_message::<organism::OrganismDied>()
+        .add_plugins(entropy_plugin)
+        .insert_resource(grass::GrassAssets::default())
+        .add_plugins(MaterialPlugin::<grass::GrassMaterial>::default())
+        .insert_resource(Time::<Fixed>::from_duration(timestep))
+        .insert_resource(general_params)
+        .insert_resource(terrain::TerrainAssets::default())
+        .insert_resource(calendar::Calendar::default())
+        .insert_resource(weather::Weather::default())
+        .insert_resource(metrics::Metrics::default())
+        .insert_resource(fire::FireState::default())
+        .insert_resource(player_inputs::ToolMode::default())
+        .init_resource::<cli::StopRequest>()
+        .init_resource::<organism::OccupancyCheck>()
+        .init_resource::<organism::OrganismIds>()
+        .insert_resource(options)
+        .add_systems(EguiPrimaryContextPass, parameters::parameter_ui_system)
+        //      .add_plugins(ScreenSpaceAmbientOcclusionPlugin)
+        .add_systems(Startup, setup)
+        .add_systems(Startup, terrain::setup_terrain)
+        .add_systems(
+            Startup,
+            snapshot::load_snapshot_system
+                .after(setup)
+                .after(terrain::setup_terrain),
+        )
+        .add_systems(Update, day_night_cycle)
+        .add_systems(Update, terrain::update_terrain_mesh_system)
+        .add_systems(
+            Update,
+            player_inputs::picking_system
+                .run_if(resource_equals(player_inputs::ToolMode::Plant))
+                .run_if(not(resource_exists::<replay::Replay>))
+                .run_if(not(egui_wants_any_keyboard_input).and(not(egui_wants_any_pointer_input))),
+        )
+        .add_systems(Update, hud::hud_system)
+        .add_systems(Update, hud::date_label_system)
+        .add_systems(Update, player_inputs::general_actions_system)
+        .add_systems(Update, metrics::export_metrics_system)
+        .add_systems(Update, snapshot::save_snapshot_system)
+        .add_systems(
+            FixedUpdate,
+            calendar::advance_calendar_system.before(organism::update_organisms_system),
+        )
+        .add_systems(
+            FixedUpdate,
+            weather::weather_system
+                .after(calendar::advance_calendar_system)
+                .before(organism::update_organisms_system),
+        )
+        .add_systems(
+            FixedUpdate,
+            temperature::temperature_system
+                .after(weather::weather_system)
+                .before(organism::update_organisms_system),
+        )
+        .add_systems(
+            FixedUpdate,
+            habitat::classify_habitat_system.after(weather::weather_system),
+        )
+        .add_systems(FixedUpdate, organism::update_organisms_system)
+        .add_systems(FixedUpdate, organism::propagate_organisms_system)
+        .add_systems(
+            FixedUpdate,
+            fire::fire_system.after(organism::update_organisms_system),
+        )
+        .add_systems(
+            FixedUpdate,
+            metrics::record_metrics_system
+                .after(weather::weather_system)
+                .after(organism::update_organisms_system),
+        )
+        .add_systems(FixedPostUpdate, organism::occupancy_system)
+        .add_systems(FixedLast, cli::end_of_run_system)
+        .add_systems(Last, terrain::clear_dirty_system);
+    Ok(app)
+}
+
+// Fields which can be visualized, observed and queried remotely.
+fn vis_fields() -> Vec<VisField> {
+    vec![
+        VisField::scalar("height", |terrain, _| &terrain.height_map),
+        VisField::scalar("vegetation density", |_, surface| &surface.veg_density)
+            .with_range(0.0, 1.0),
+        VisField::scalar("moisture", |_, surface| &surface.moisture).with_range(0.0, 1.0),
+        VisField::scalar("nutrients", |_, surface| &surface.nutrients),
+        VisField::scalar("burning", |_, surface| &surface.burn_time)
+            .with_range(0.0, fire::FireParameters::default().burn_duration),
+        VisField::scalar("temperature", |_, surface| &surface.temperature)
+            .with_color_scheme(color_map::ColorScheme::Rainbow),
+        VisField::categorical(
+            "habitat",
+            |_, surface| &surface.habitat,
+            habitat::Habitat::ALL
+                .iter()
+                .map(|h| (h.name(), h.color()))
+                .collect(),
+        ),
+    ]
+}
+/*
+fn setup_world(world: &mut World){
+    world.insert_resource(grass::GrassAssets {
+        mesh: Handle::,
+        material: materials.add(Color::linear_rgb(0.0, 1.0, 0.0)),
+    });
+}*/
+
+/// set scene
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut ext_materials: ResMut<Assets<grass::GrassMaterial>>,
+    mut grass_assets: ResMut<grass::GrassAssets>,
+    mut scattering_mediums: ResMut<Assets<pbr::ScatteringMedium>>,
+) {
+    grass_assets.mesh = meshes.add(create_grass_mesh(4, 0.15));
+    grass_assets.material = ext_materials.add(create_grass_material());
+
+    // point light
+    commands.spawn((
+        PointLight {
+            shadows_enabled: true,
+
+            ..default()
+        },
+        Transform::from_xyz(domain::half_size().x as f32, 2.0, domain::half_size().y as f32),
+    ));
+
+    // sun
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::RAW_SUNLIGHT,
+            shadows_enabled: true,
+            ..default()
+        },
+        light::VolumetricLight,
+        Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quat::from_rotation_x(-PI / 4.),
+            ..default()
+        },
+        light::CascadeShadowConfigBuilder { ..default() }.build(),
+    ));
+
+    // camera
+    commands.spawn((
+        Camera3d::default(),
+        Transform::from_xyz(domain::half_size().x as f32, 4.5, domain::half_size().y as f32)
+            .looking_at(Vec3::ZERO, Vec3::Y),
+        CameraController::default(),
+        Msaa::Off,
+        pbr::ScreenSpaceAmbientOcclusion {
+            quality_level: pbr::ScreenSpaceAmbientOcclusionQualityLevel::High,
+            constant_object_thickness: 4.0,
+        },
+        // Earthlike atmosphere
+        pbr::Atmosphere::earthlike(scattering_mediums.add(pbr::ScatteringMedium::default())),
+        // Can be adjusted to change the scene scale and rendering quality
+        pbr::AtmosphereSettings::default(),
+        // The directional light illuminance used in this scene
+        // (the one recommended for use with this feature) is
+        // quite bright, so raising the exposure compensation helps
+        // bring the scene to a nicer brightness range.
+        camera::Exposure { ev100: 14.0 },
+        bevy::core_pipeline::tonemapping::Tonemapping::None,
+        // Bloom gives the sun a much more natural look.
+        Bloom::NATURAL,
+        // Enables the atmosphere to drive reflections and ambient lighting (IBL) for this view
+        light::AtmosphereEnvironmentMapLight::default(),
+        light::VolumetricFog {
+            ambient_intensity: 0.0,
+            ..default()
+        },
+    ));
+
+    // spawn the fog volume
+    /*   commands.spawn((
+        light::FogVolume::default(),
+        Transform::from_scale(Vec3::new(10.0, 1.0, 10.0)).with_translation(Vec3::Y * 0.5),
+    ));*/
+
+    // game speed indicator
+    commands.spawn((
+        Text::new("game speed: 0.0"),
+        TextLayout::new_with_justify(Justify::Right),
+        Node {
+            position_type: PositionType::Relative,
+            bottom: px(5),
+            left: px(5),
+            align_self: AlignSelf::End,
+            ..default()
+        },
+        hud::GameSpeedLabel::default(),
+    ));
+
+    // date
+    commands.spawn((
+        Text::new("spring, day 1, year 1"),
+        TextLayout::new_with_justify(Justify::Right),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: px(5),
+            right: px(5),
+            ..default()
+        },
+        hud::DateLabel::default(),
+    ));
+}
+
+fn day_night_cycle(
+    mut suns: Query<&mut Transform, With<DirectionalLight>>,
+    calendar: Res<calendar::Calendar>,
+    params: Res<parameters::GeneralParameters>,
+) {
+    let sun_dir = calendar.sun_direction(&params.sun);
+    suns.iter_mut().for_each(|mut tf| {
+        // the light shines along its forward direction
+        tf.rotation = Quat::from_rotation_arc(Vec3::NEG_Z, -sun_dir);
+    });
+}
@@ -0,0 +1,274 @@
+//! Lineage of the organisms. Each organism carries a persistent id together with its parent,
+//! generation and founder, which are kept when it is restored by undo or from a snapshot. The
+//! records remain after the death of an organism. Organisms without a parent, e.g. planted ones,
+//! found a new clone.
+//! F8 writes the family trees in Newick format, all records as JSON and statistics of the clones
+//! to the output directory, which also happens at the end of a run.
+
+use bevy::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::cli::{self, Options};
+use crate::organism::{DeathCause, OrganismBorn, OrganismDied, OrganismId};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LineageRecord {
+    pub id: u64,
+    pub parent: Option<u64>,
+    // number of ancestors
+    pub generation: u32,
+    // first ancestor
+    pub founder: u64,
+    pub position: [f32; 2],
+    pub birth_time: f32, // [s]
+    pub death_time: Option<f32>,
+    pub death_cause: Option<DeathCause>,
+}
+
+// All descendants of a founder including itself.
+pub struct CloneStats {
+    pub founder: u64,
+    pub size: usize,
+    pub living: usize,
+    pub max_generation: u32,
+    // of the living members
+    pub centroid: Vec2,
+    pub radius_of_gyration: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct LineageStore {
+    // by id, which orders the exports
+    records: BTreeMap<u64, LineageRecord>,
+}
+
+impl LineageStore {
+    fn add(&mut self, birth: &OrganismBorn, time: f32) {
+        let id = birth.id.0;
+        // restored organisms are alive again
+        if let Some(record) = self.records.get_mut(&id) {
+            record.position = birth.position.to_array();
+            record.death_time = None;
+            record.death_cause = None;
+            return;
+        }
+        let lineage = &birth.lineage;
+        self.records.insert(
+            id,
+            LineageRecord {
+                id,
+                parent: lineage.parent.map(|parent| parent.0),
+                generation: lineage.generation,
+                founder: lineage.founder.map_or(id, |founder| founder.0),
+                position: birth.position.to_array(),
+                birth_time: time,
+                death_time: None,
+                death_cause: None,
+            },
+        );
+    }
+
+    fn remove(&mut self, id: OrganismId, cause: DeathCause, time: f32) {
+        if let Some(record) = self.records.get_mut(&id.0) {
+            record.death_time = Some(time);
+            record.death_cause = Some(cause);
+        }
+    }
+
+    // Parent of a record if it is known, organisms restored from a snapshot can descend from
+    // ones which were not recorded.
+    fn known_parent(&self, record: &LineageRecord) -> Option<&LineageRecord> {
+        record.parent.and_then(|parent| self.records.get(&parent))
+    }
+
+    fn children(&self) -> HashMap<u64, Vec<u64>> {
+        let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+        for record in self.records.values() {
+            if let Some(parent) = self.known_parent(record) {
+                children.entry(parent.id).or_default().push(record.id);
+            }
+        }
+        children
+    }
+
+    // Newick label of a node with the time between the births of its parent and itself as
+    // branch length.
+    fn write_node(&self, id: u64, out: &mut String) {
+        let record = &self.records[&id];
+        out.push_str(&id.to_string());
+        if let Some(parent) = self.known_parent(record) {
+            let length = record.birth_time - parent.birth_time;
+            out.push_str(&format!(":{length}"));
+        }
+    }
+
+    // One tree per organism without a known parent and line.
+    pub fn write_newick(&self, path: &Path) -> io::Result<()> {
+        enum Visit {
+            Enter(u64),
+            Exit(u64),
+            Separator,
+        }
+
+        let children = self.children();
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut tree = String::new();
+        let no_children = Vec::new();
+        let children_of = |id: u64| children.get(&id).unwrap_or(&no_children);
+        let roots = self
+            .records
+            .values()
+            .filter(|r| self.known_parent(r).is_none());
+        for root in roots {
+            tree.clear();
+            // the trees can be deeper than the call stack allows for recursion
+            let mut stack = vec![Visit::Enter(root.id)];
+            while let Some(visit) = stack.pop() {
+                match visit {
+                    Visit::Enter(id) if children_of(id).is_empty() => {
+                        self.write_node(id, &mut tree);
+                    }
+                    Visit::Enter(id) => {
+                        tree.push('(');
+                        stack.push(Visit::Exit(id));
+                        for (i, &child) in children_of(id).iter().enumerate().rev() {
+                            stack.push(Visit::Enter(child));
+                            if i > 0 {
+                                stack.push(Visit::Separator);
+                            }
+                        }
+                    }
+                    Visit::Exit(id) => {
+                        tree.push(')');
+                        self.write_node(id, &mut tree);
+                    }
+                    Visit::Separator => tree.push(','),
+                }
+            }
+            writeln!(writer, "{tree};")?;
+        }
+        writer.flush()
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        let records: Vec<&LineageRecord> = self.records.values().collect();
+        let text = serde_json::to_string(&records).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+
+    pub fn clone_stats(&self) -> Vec<CloneStats> {
+        let mut clones: Vec<CloneStats> = Vec::new();
+        let mut index = HashMap::new();
+        let mut positions: Vec<Vec<Vec2>> = Vec::new();
+        for record in self.records.values() {
+            let i = *index.entry(record.founder).or_insert_with(|| {
+                clones.push(CloneStats {
+                    founder: record.founder,
+                    size: 0,
+                    living: 0,
+                    max_generation: 0,
+                    centroid: Vec2::ZERO,
+                    radius_of_gyration: 0.0,
+                });
+                positions.push(Vec::new());
+                clones.len() - 1
+            });
+            let stats = &mut clones[i];
+            stats.size += 1;
+            stats.max_generation = stats.max_generation.max(record.generation);
+            if record.death_time.is_none() {
+                stats.living += 1;
+                positions[i].push(Vec2::from_array(record.position));
+            }
+        }
+
+        for (stats, positions) in clones.iter_mut().zip(positions) {
+            if positions.is_empty() {
+                continue;
+            }
+            let n = positions.len() as f32;
+            stats.centroid = positions.iter().sum::<Vec2>() / n;
+            stats.radius_of_gyration = (positions
+                .iter()
+                .map(|p| p.distance_squared(stats.centroid))
+                .sum::<f32>()
+                / n)
+                .sqrt();
+        }
+        clones
+    }
+
+    pub fn write_clone_csv(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "founder,size,living,max_generation,centroid_x,centroid_y,radius_of_gyration"
+        )?;
+        for s in self.clone_stats() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                s.founder,
+                s.size,
+                s.living,
+                s.max_generation,
+                s.centroid.x,
+                s.centroid.y,
+                s.radius_of_gyration
+            )?;
+        }
+        writer.flush()
+    }
+
+    // Write all exports to the output directory.
+    pub fn export(&self, options: &Options) -> io::Result<()> {
+        self.write_newick(&options.output_path(cli::LINEAGE_TREE_FILE)?)?;
+        self.write_json(&options.output_path(cli::LINEAGE_FILE)?)?;
+        self.write_clone_csv(&options.output_path(cli::CLONES_FILE)?)
+    }
+}
+
+pub struct LineagePlugin;
+
+impl Plugin for LineagePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LineageStore>()
+            .add_systems(Last, track_lineage_system)
+            .add_systems(Update, export_lineage_system);
+    }
+}
+
+// Births are handled first, so that a parent which died in the same frame is still known.
+pub fn track_lineage_system(
+    fixed_time: Res<Time<Fixed>>,
+    mut store: ResMut<LineageStore>,
+    mut births: MessageReader<OrganismBorn>,
+    mut deaths: MessageReader<OrganismDied>,
+) {
+    let time = fixed_time.elapsed_secs();
+    for birth in births.read() {
+        store.add(birth, time);
+    }
+    for death in deaths.read() {
+        store.remove(death.id, death.cause, time);
+    }
+}
+
+pub fn export_lineage_system(
+    key_input: Res<ButtonInput<KeyCode>>,
+    store: Res<LineageStore>,
+    options: Res<cli::Options>,
+) {
+    if !key_input.just_pressed(KeyCode::F8) {
+        return;
+    }
+
+    match store.export(&options) {
+        Ok(()) => info!("lineage written to {}", options.output_dir.display()),
+        Err(err) => error!("failed to write lineage: {}", err),
+    }
+}
@@ -0,0 +1,241 @@
+use bevy::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::cli;
+use crate::domain;
+use crate::fire::FireState;
+use crate::organism::Organism;
+use crate::spatial::{self, SpatialStatistics};
+use crate::terrain::Surface;
+use crate::weather::Weather;
+
+// initial simulated time between two samples [s]
+const SAMPLE_INTERVAL: f32 = 1.0;
+// once reached, every second sample is dropped and the interval doubled, an even number
+const MAX_SAMPLES: usize = 10_000;
+// the population is in equilibrium once it stays within this relative distance of its final mean
+const EQUILIBRIUM_TOLERANCE: f32 = 0.1;
+// fraction of the samples at the end of a run which determine the final mean
+const EQUILIBRIUM_WINDOW: f32 = 0.25;
+// minimum vegetation density of a covered cell
+pub const COVER_THRESHOLD: f32 = 0.05;
+
+pub struct MetricsSample {
+    pub time: f32, // [s]
+    pub population: usize,
+    pub precipitation: f32,
+    pub temperature: f32,
+    pub is_drought: bool,
+    pub mean_moisture: f32,
+    pub burning_cells: usize,
+    pub mean_nearest_neighbour: Option<f32>,
+    pub num_patches: usize,
+    pub largest_patch: f32,
+}
+
+// Time series of the simulation state.
+#[derive(Resource)]
+pub struct Metrics {
+    pub samples: Vec<MetricsSample>,
+    elapsed: f32,
+    next_sample: f32,
+    sample_interval: f32,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            samples: Vec::new(),
+            elapsed: 0.0,
+            next_sample: 0.0,
+            sample_interval: SAMPLE_INTERVAL,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn write_csv(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "time,population,precipitation,temperature,drought,mean_moisture,burning_cells,\
+             mean_nearest_neighbour,patches,largest_patch"
+        )?;
+        for s in &self.samples {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{}",
+                s.time,
+                s.population,
+                s.precipitation,
+                s.temperature,
+                s.is_drought as u8,
+                s.mean_moisture,
+                s.burning_cells,
+                // empty with less than two organisms
+                s.mean_nearest_neighbour
+                    .map_or(String::new(), |distance| distance.to_string()),
+                s.num_patches,
+                s.largest_patch
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+// Key figures of a finished run.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RunSummary {
+    pub final_population: usize,
+    // mean population over the last samples
+    pub equilibrium_population: f32,
+    // simulated time after which the population stays close to its equilibrium [s]
+    pub equilibrium_time: Option<f32>,
+    // fraction of the cells with vegetation
+    pub cover: f32,
+    pub mean_density: f32,
+    // Clark-Evans ratio of the mean nearest neighbour distance to the one of a random
+    // distribution with the same density, <1 for clustered and >1 for regular patterns
+    pub aggregation: Option<f32>,
+}
+
+impl RunSummary {
+    pub fn new(metrics: &Metrics, surface: &Surface, positions: Vec<Vec2>) -> Self {
+        let populations: Vec<f32> = metrics
+            .samples
+            .iter()
+            .map(|s| s.population as f32)
+            .collect();
+        let window = ((populations.len() as f32 * EQUILIBRIUM_WINDOW).ceil() as usize).max(1);
+        let tail = &populations[populations.len().saturating_sub(window)..];
+        let equilibrium_population = tail.iter().sum::<f32>() / tail.len().max(1) as f32;
+
+        // the first sample after which all samples are close to the equilibrium
+        let tolerance = EQUILIBRIUM_TOLERANCE * equilibrium_population;
+        let num_unsettled = populations
+            .iter()
+            .rposition(|p| (p - equilibrium_population).abs() > tolerance)
+            .map_or(0, |idx| idx + 1);
+        let equilibrium_time = metrics.samples.get(num_unsettled).map(|s| s.time);
+
+        let num_covered = surface
+            .veg_density
+            .iter()
+            .filter(|&&density| density > COVER_THRESHOLD)
+            .count();
+        let num_cells = surface.veg_density.num_elem() as f32;
+
+        RunSummary {
+            final_population: positions.len(),
+            equilibrium_population,
+            equilibrium_time,
+            cover: num_covered as f32 / num_cells,
+            mean_density: surface.veg_density.iter().sum::<f32>() / num_cells,
+            aggregation: clark_evans_ratio(positions),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        ron::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, text)
+    }
+}
+
+// Ratio of the observed to the expected mean nearest neighbour distance.
+// Edge effects are ignored, which slightly overestimates the ratio.
+fn clark_evans_ratio(positions: Vec<Vec2>) -> Option<f32> {
+    let distances = spatial::nearest_neighbour_distances(positions);
+    if distances.is_empty() {
+        return None;
+    }
+    let sum_distance = distances.iter().sum::<f32>();
+
+    let n = distances.len() as f32;
+    let area = domain::size_f32().element_product();
+    let expected = 0.5 / (n / area).sqrt();
+    Some(sum_distance / n / expected)
+}
+
+pub fn record_metrics_system(
+    mut metrics: ResMut<Metrics>,
+    time: Res<Time>,
+    organism_query: Query<&Transform, With<Organism>>,
+    surface_query: Query<&Surface>,
+    weather: Res<Weather>,
+    fire: Res<FireState>,
+) {
+    metrics.elapsed += time.delta_secs();
+    if metrics.elapsed < metrics.next_sample {
+        return;
+    }
+    metrics.next_sample += metrics.sample_interval;
+
+    let surface = surface_query.single().unwrap();
+    let positions: Vec<Vec2> = organism_query
+        .iter()
+        .map(|transform| transform.translation.xz())
+        .collect();
+    let distances = spatial::nearest_neighbour_distances(positions);
+    let patch_areas = spatial::patch_areas(&surface.veg_density);
+    let sample = MetricsSample {
+        time: metrics.elapsed,
+        population: organism_query.iter().count(),
+        precipitation: weather.precipitation,
+        temperature: weather.temperature,
+        is_drought: weather.is_drought(),
+        mean_moisture: surface.moisture.iter().sum::<f32>() / surface.moisture.num_elem() as f32,
+        burning_cells: fire.num_burning(),
+        mean_nearest_neighbour: (!distances.is_empty())
+            .then(|| distances.iter().sum::<f32>() / distances.len() as f32),
+        num_patches: patch_areas.len(),
+        largest_patch: patch_areas.first().copied().unwrap_or(0.0),
+    };
+    metrics.samples.push(sample);
+
+    // keep long runs bounded with evenly spaced samples
+    if metrics.samples.len() >= MAX_SAMPLES {
+        let mut idx = 0;
+        metrics.samples.retain(|_| {
+            idx += 1;
+            idx % 2 == 1
+        });
+        metrics.sample_interval *= 2.0;
+    }
+}
+
+pub fn export_metrics_system(
+    key_input: Res<ButtonInput<KeyCode>>,
+    metrics: Res<Metrics>,
+    options: Res<cli::Options>,
+    organism_query: Query<&Transform, With<Organism>>,
+    surface_query: Query<&Surface>,
+) {
+    if !key_input.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let spatial_statistics = SpatialStatistics::new(
+        organism_query
+            .iter()
+            .map(|transform| transform.translation.xz())
+            .collect(),
+        &surface_query.single().unwrap().veg_density,
+    );
+    let result = options
+        .output_path(cli::METRICS_FILE)
+        .and_then(|path| metrics.write_csv(&path))
+        .and_then(|_| options.output_path(cli::SPATIAL_FILE))
+        .and_then(|path| spatial_statistics.write_csv(&path));
+    match result {
+        Ok(()) => info!("metrics written to {}", options.output_dir.display()),
+        Err(err) => error!("failed to write metrics: {}", err),
+    }
+}
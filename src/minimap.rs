@@ -0,0 +1,112 @@
+//! Top-down minimap of the active field overlay.
+//! The area visible to the camera is outlined and clicking on the map moves the camera there.
+
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, EguiTextureHandle, egui};
+
+use crate::domain;
+use crate::field_vis::FieldVisState;
+use crate::terrain::TerrainAssets;
+
+const MINIMAP_SIZE: f32 = 200.0;
+// distance along rays which do not hit the ground in multiples of the domain size
+const MAX_VIEW_DISTANCE: f32 = 2.0;
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(EguiPrimaryContextPass, minimap_ui_system);
+    }
+}
+
+// Point where the ray hits the plane at height 0, or a distant point on the ray if it never does.
+fn ground_point(ray: Ray3d) -> Vec2 {
+    let max_distance = MAX_VIEW_DISTANCE * domain::size_f32().x;
+    let distance = ray
+        .intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))
+        .unwrap_or(max_distance)
+        .min(max_distance);
+    ray.get_point(distance).xz()
+}
+
+pub fn minimap_ui_system(
+    mut contexts: EguiContexts,
+    field_vis_state: Res<FieldVisState>,
+    terrain_assets: Res<TerrainAssets>,
+    mut camera_query: Query<(&Camera, &GlobalTransform, &mut Transform), With<Camera3d>>,
+) -> Result {
+    if field_vis_state.active.is_none() {
+        return Ok(());
+    }
+
+    let texture_id =
+        contexts.add_image(EguiTextureHandle::Weak(terrain_assets.field_vis_image.id()));
+    let (camera, camera_global_transform, mut camera_transform) = camera_query.single_mut()?;
+
+    // ground points of the viewport corners and center
+    let viewport_rays = camera.logical_viewport_rect().map(|rect| {
+        [
+            rect.min,
+            vec2(rect.max.x, rect.min.y),
+            rect.max,
+            vec2(rect.min.x, rect.max.y),
+            rect.center(),
+        ]
+        .map(|p| camera.viewport_to_world(camera_global_transform, p).ok())
+    });
+
+    egui::Window::new("Minimap")
+        .resizable(false)
+        .anchor(Align2::RIGHT_TOP, egui::vec2(-5.0, 5.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            let response = ui.add(
+                egui::Image::new(egui::load::SizedTexture::new(
+                    texture_id,
+                    egui::vec2(MINIMAP_SIZE, MINIMAP_SIZE),
+                ))
+                .sense(egui::Sense::click()),
+            );
+            let rect = response.rect;
+            let to_map = |p: Vec2| {
+                let t = p / domain::size_f32();
+                egui::pos2(
+                    rect.left() + t.x * rect.width(),
+                    rect.top() + t.y * rect.height(),
+                )
+            };
+
+            // camera frustum
+            let painter = ui.painter_at(rect);
+            if let Some([Some(r0), Some(r1), Some(r2), Some(r3), _]) = viewport_rays {
+                let corners = [r0, r1, r2, r3].map(|ray| to_map(ground_point(ray)));
+                painter.add(egui::Shape::closed_line(
+                    corners.to_vec(),
+                    egui::Stroke::new(1.5, egui::Color32::WHITE),
+                ));
+            }
+            painter.circle_filled(
+                to_map(camera_transform.translation.xz()),
+                3.0,
+                egui::Color32::WHITE,
+            );
+
+            // jump to the clicked position, keeping the view direction
+            if response.clicked()
+                && let Some(pos) = response.interact_pointer_pos()
+            {
+                let target = vec2(
+                    (pos.x - rect.left()) / rect.width(),
+                    (pos.y - rect.top()) / rect.height(),
+                ) * domain::size_f32();
+                let center = viewport_rays
+                    .and_then(|rays| rays[4])
+                    .map_or(camera_transform.translation.xz(), ground_point);
+                let offset = target - center;
+                camera_transform.translation += Vec3::new(offset.x, 0.0, offset.y);
+            }
+        });
+
+    Ok(())
+}
@@ -1,4 +1,7 @@
+use crate::calendar;
 use crate::domain;
+use crate::grass;
+use crate::habitat;
 use crate::parameters;
 use crate::{Surface, Terrain};
 use bevy::prelude::*;
@@ -7,32 +10,257 @@ use bevy_rand::prelude::*;
 use rand::prelude::*;
 use std::f32::consts::PI;
 
-#[derive(Component, Default)]
+// Identifies an organism across despawns, e.g. when it is restored by undo or from a snapshot.
+#[derive(
+    PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone, Debug, serde::Serialize, serde::Deserialize,
+)]
+pub struct OrganismId(pub u64);
+
+impl OrganismId {
+    // id of organisms from snapshots which were saved without ids
+    const UNASSIGNED: OrganismId = OrganismId(u64::MAX);
+
+    fn unassigned() -> Self {
+        Self::UNASSIGNED
+    }
+}
+
+// Allocates the ids of the organisms of an app, so that runs with the same seed give the same ids.
+#[derive(Resource, Default)]
+pub struct OrganismIds {
+    // next unused id
+    next: u64,
+}
+
+impl OrganismIds {
+    pub fn allocate(&mut self) -> OrganismId {
+        let id = OrganismId(self.next);
+        self.next += 1;
+        id
+    }
+
+    // Make sure that newly allocated ids differ from the one of a restored organism. Organisms
+    // without an id get a new one.
+    fn restore(&mut self, organism: &mut Organism) {
+        if organism.id == OrganismId::UNASSIGNED {
+            organism.id = self.allocate();
+        } else {
+            self.next = self.next.max(organism.id.0 + 1);
+        }
+    }
+}
+
+// Ancestry of an organism, kept together with its id.
+#[derive(Default, Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Lineage {
+    // the organism which produced the seed, none for planted organisms
+    pub parent: Option<OrganismId>,
+    // number of ancestors
+    pub generation: u32,
+    // first ancestor, none if the organism has no parent
+    pub founder: Option<OrganismId>,
+}
+
+#[derive(Component, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Organism {
+    // organisms of snapshots without ids get new ones when they are restored
+    #[serde(default = "OrganismId::unassigned")]
+    id: OrganismId,
+    #[serde(default)]
+    lineage: Lineage,
     age: f32, // [s]
+    size: f32,
     surface_area: f32,
+    water_deficit: f32, // [s]
+}
+
+impl Organism {
+    // A seedling without ancestors.
+    pub fn new(ids: &mut OrganismIds) -> Self {
+        Organism {
+            id: ids.allocate(),
+            lineage: Lineage::default(),
+            age: 0.0,
+            size: 0.0,
+            surface_area: 0.0,
+            water_deficit: 0.0,
+        }
+    }
+
+    pub fn id(&self) -> OrganismId {
+        self.id
+    }
+
+    // A seedling with a new id which descends from this organism.
+    pub fn offspring(&self, ids: &mut OrganismIds) -> Self {
+        Organism {
+            lineage: self.child_lineage(),
+            ..Organism::new(ids)
+        }
+    }
+
+    // Copy with a new id which descends from this organism, e.g. to place a clone next to it.
+    pub fn duplicate(&self, ids: &mut OrganismIds) -> Self {
+        Organism {
+            id: ids.allocate(),
+            lineage: self.child_lineage(),
+            ..self.clone()
+        }
+    }
+
+    fn child_lineage(&self) -> Lineage {
+        Lineage {
+            parent: Some(self.id),
+            generation: self.lineage.generation + 1,
+            founder: Some(self.founder()),
+        }
+    }
+
+    pub fn lineage(&self) -> &Lineage {
+        &self.lineage
+    }
+
+    pub fn founder(&self) -> OrganismId {
+        self.lineage.founder.unwrap_or(self.id)
+    }
+
+    pub fn age(&self) -> f32 {
+        self.age
+    }
+
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        self.surface_area
+    }
+
+    pub fn water_deficit(&self) -> f32 {
+        self.water_deficit
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum DeathCause {
+    Age,
+    Drought,
+    Fire,
+    // by the user or a tool like a script
+    Removed,
+}
+
+// Written for each spawned organism, including restored ones which keep their id.
+#[derive(Message, Copy, Clone, Debug)]
+pub struct OrganismBorn {
+    pub id: OrganismId,
+    pub lineage: Lineage,
+    pub position: Vec2,
+}
+
+// Written for each removed organism.
+#[derive(Message, Copy, Clone, Debug)]
+pub struct OrganismDied {
+    pub id: OrganismId,
+    pub cause: DeathCause,
+    pub age: f32, // [s]
 }
 
 const MAX_SIZE: f32 = 1.0;
 
+// Despawn the organism and release its surface area.
+pub fn remove_organism(
+    commands: &mut Commands,
+    surface: &mut Surface,
+    id: Entity,
+    transform: &Transform,
+    organism: &Organism,
+    cause: DeathCause,
+) {
+    release_surface_area(
+        &mut surface.veg_density,
+        transform.translation.xz(),
+        organism.surface_area,
+    );
+    commands.entity(id).despawn();
+    commands.write_message(OrganismDied {
+        id: organism.id,
+        cause,
+        age: organism.age,
+    });
+}
+
+// Spawn a copy of the organism with the given transform and occupy its surface area. The copy
+// keeps the id, so that a removed organism can be restored.
+pub fn spawn_copy(
+    commands: &mut Commands,
+    ids: &mut OrganismIds,
+    surface: &mut Surface,
+    grass_assets: &crate::GrassAssets,
+    transform: Transform,
+    organism: &Organism,
+) -> Entity {
+    let mut organism = organism.clone();
+    ids.restore(&mut organism);
+    let (organism_id, lineage) = (organism.id, organism.lineage);
+    surface
+        .veg_density
+        .add_kernel(transform.translation.xz(), organism.surface_area, 1.0);
+    let id = commands
+        .spawn((
+            Mesh3d(grass_assets.mesh.clone()),
+            bevy::light::NotShadowCaster::default(),
+            MeshMaterial3d(grass_assets.material.clone()),
+            transform,
+            organism,
+        ))
+        .id();
+    commands.write_message(OrganismBorn {
+        id: organism_id,
+        lineage,
+        position: transform.translation.xz(),
+    });
+    id
+}
+
 pub fn update_organisms_system(
     time: Res<Time>,
     mut commands: Commands,
     mut surface_query: Query<&mut Surface>,
     mut organism_query: Query<(Entity, &mut Transform, &mut Organism)>,
     general_params: Res<parameters::GeneralParameters>,
+    calendar: Res<calendar::Calendar>,
 ) {
     let mut surface = surface_query.single_mut().unwrap();
+    let growth_factor = calendar.growth_factor(
+        general_params.sun.latitude,
+        general_params.calendar.seasonality,
+    );
 
     for (id, mut transform, mut organism) in organism_query.iter_mut() {
         let dt = time.delta_secs();
+        let center = transform.translation.xz();
+        let water_factor = general_params
+            .grass
+            .water_factor(surface.moisture.get_bilinear(center));
+        let temperature_factor = general_params
+            .grass
+            .temperature_factor(surface.temperature.get_bilinear(center));
+        let nutrient_factor =
+            1.0 + general_params.grass.nutrient_boost * surface.nutrients.get_bilinear(center);
+        // accumulates under water stress and recovers otherwise
+        organism.water_deficit =
+            (organism.water_deficit + dt * (1.0 - 2.0 * water_factor)).max(0.0);
+
         // still growing
-        if organism.age < MAX_SIZE {
-            let delta = dt.min(1.0 - organism.age);
-            transform.scale = Vec3::ONE * (organism.age + delta);
+        if organism.size < MAX_SIZE {
+            let delta = (dt * growth_factor * water_factor * temperature_factor * nutrient_factor)
+                .min(MAX_SIZE - organism.size);
+            organism.size += delta;
+            transform.scale = Vec3::ONE * organism.size;
 
             // add surface area usage
-            let center = transform.translation.xz();
             let delta_area = delta * general_params.grass.surface_area;
             surface
                 .veg_density
@@ -45,14 +273,25 @@ pub fn update_organisms_system(
 
         organism.age += time.delta_secs();
 
-        // death
+        // death by age or drought
         if organism.age > general_params.grass.max_age {
-            let p = transform.translation.xz();
-            surface
-                .veg_density
-                .add_kernel(p, organism.surface_area, -1.0);
-            // delete entity
-            commands.entity(id).despawn();
+            remove_organism(
+                &mut commands,
+                &mut surface,
+                id,
+                &transform,
+                &organism,
+                DeathCause::Age,
+            );
+        } else if organism.water_deficit > general_params.grass.drought_tolerance {
+            remove_organism(
+                &mut commands,
+                &mut surface,
+                id,
+                &transform,
+                &organism,
+                DeathCause::Drought,
+            );
         }
     }
 }
@@ -67,51 +306,398 @@ pub fn propagate_organisms_system(
     terrain_query: Query<&Terrain>,
     surface_query: Query<&Surface>,
     mut rng: Single<&mut WyRand, With<GlobalRng>>,
+    mut ids: ResMut<OrganismIds>,
     grass_assets: Res<crate::GrassAssets>,
     general_params: Res<parameters::GeneralParameters>,
+    calendar: Res<calendar::Calendar>,
 ) {
     let terrain = terrain_query.single().unwrap();
     let surface = surface_query.single().unwrap();
+    let spawn_prop = SPAWN_PROP
+        * calendar.growth_factor(
+            general_params.sun.latitude,
+            general_params.calendar.seasonality,
+        );
 
     for (transform, organism) in organism_query.iter() {
         if organism.age < MIN_PROPAGATION_AGE {
             continue;
         }
-        if rng.random::<f32>() >= SPAWN_PROP {
+        if rng.random::<f32>() >= spawn_prop {
             continue;
         }
 
         let area = Circle::new(general_params.grass.spawn_radius);
         let p = area.sample_interior(&mut rng) + transform.translation.xz();
-        if !domain::BOUNDS.contains(p) {
+        if !domain::bounds().contains(p) {
             continue;
         }
         if surface.veg_density.get_bilinear(p) > 0.5 {
             continue;
         }
+        // seedlings need water, a suitable temperature and habitat
+        let grass_params = &general_params.grass;
+        let habitat = habitat::Habitat::from_id(surface.habitat.get_nearest(p));
+        let establishment = grass_params.water_factor(surface.moisture.get_bilinear(p))
+            * grass_params.temperature_factor(surface.temperature.get_bilinear(p))
+            * grass_params.habitat_preferences.suitability(habitat);
+        if rng.random::<f32>() >= establishment {
+            continue;
+        }
 
         /*    let axis_circle = Circle::new(grass::ORIENTATION_MAX_RADIUS);
         let tip = axis_circle.sample_interior(&mut rng);
         let axis = Vec3::new(tip.x, 1.0, tip.y).normalize();*/
 
-        commands.spawn((
+        spawn_seedling(
+            &mut commands,
+            &mut ids,
+            &grass_assets,
+            terrain,
+            p,
+            Some(organism),
+            &general_params.grass,
+            &mut rng,
+        );
+    }
+}
+
+// Spawn a new organism at the position p in the domain with a random orientation.
+pub fn spawn_seedling(
+    commands: &mut Commands,
+    ids: &mut OrganismIds,
+    grass_assets: &crate::GrassAssets,
+    terrain: &Terrain,
+    p: Vec2,
+    parent: Option<&Organism>,
+    grass_params: &grass::GrassParameters,
+    rng: &mut WyRand,
+) -> (Entity, OrganismId) {
+    let organism = match parent {
+        Some(parent) => parent.offspring(ids),
+        None => Organism::new(ids),
+    };
+    let (organism_id, lineage) = (organism.id, organism.lineage);
+    let id = commands
+        .spawn((
             Mesh3d(grass_assets.mesh.clone()),
             bevy::light::NotShadowCaster::default(),
             MeshMaterial3d(grass_assets.material.clone()),
             Transform::from_translation(Vec3::new(
                 p.x,
-                terrain.height_map.get_bilinear(p) - general_params.grass.below_surface_depth,
+                terrain.height_map.get_bilinear(p) - grass_params.below_surface_depth,
                 p.y,
             ))
             .with_scale(Vec3::ZERO)
             .with_rotation(Quat::from_euler(
                 EulerRot::XYZEx,
-                (rng.random::<f32>() - 0.5) * PI * general_params.grass.orientation_max_angle,
+                (rng.random::<f32>() - 0.5) * PI * grass_params.orientation_max_angle,
                 rng.random::<f32>() * 2.0 * PI,
                 0.0,
             )),
             //    .with_rotation(Quat::from_axis_angle(axis, rng.random::<f32>() * 2.0 * PI)),
-            Organism::default(),
-        ));
+            organism,
+        ))
+        .id();
+    commands.write_message(OrganismBorn {
+        id: organism_id,
+        lineage,
+        position: p,
+    });
+    (id, organism_id)
+}
+
+// simulated time between two rebuilds of the vegetation density [s]
+const OCCUPANCY_CHECK_INTERVAL: f32 = 10.0;
+// largest difference to the rebuilt vegetation density which is expected from rounding
+const OCCUPANCY_TOLERANCE: f32 = 1e-4;
+
+// Subtract the kernel of a removed organism. Residuals of the rounding errors are cleared, so
+// that the density of cells without organisms is exactly zero.
+fn release_surface_area(veg_density: &mut domain::Field<f32>, pos: Vec2, surface_area: f32) {
+    veg_density.apply_kernel(pos, surface_area, |density, weight| {
+        *density -= weight;
+        if *density < OCCUPANCY_TOLERANCE {
+            *density = 0.0;
+        }
+    });
+}
+
+// The vegetation density is the sum of the kernels of all organisms. It is tracked incrementally
+// while organisms grow and die, which accumulates rounding errors.
+#[derive(Resource, Default)]
+pub struct OccupancyCheck {
+    elapsed: f32,
+    // largest difference of the tracked to the rebuilt density at the last check
+    pub max_drift: f32,
+    // cells with a negative tracked density at the last check
+    pub num_negative: usize,
+}
+
+// Vegetation density of the given organisms computed from scratch.
+pub fn rebuild_occupancy<'a>(
+    veg_density: &domain::Field<f32>,
+    organisms: impl Iterator<Item = (&'a Transform, &'a Organism)>,
+) -> domain::Field<f32> {
+    let mut occupancy = veg_density.clone();
+    occupancy.fill(0.0);
+    for (transform, organism) in organisms {
+        occupancy.add_kernel(transform.translation.xz(), organism.surface_area, 1.0);
+    }
+    occupancy
+}
+
+// Periodically compare the tracked vegetation density with the one of the live organisms, report
+// the drift and replace it. Only the changed cells are marked dirty. Runs after the commands of
+// the fixed step, so that removed organisms are despawned and copies are spawned.
+pub fn occupancy_system(
+    time: Res<Time>,
+    mut check: ResMut<OccupancyCheck>,
+    mut surface_query: Query<&mut Surface>,
+    organism_query: Query<(&Transform, &Organism)>,
+) {
+    check.elapsed += time.delta_secs();
+    if check.elapsed < OCCUPANCY_CHECK_INTERVAL {
+        return;
+    }
+    check.elapsed = 0.0;
+
+    let mut surface = surface_query.single_mut().unwrap();
+    let occupancy = rebuild_occupancy(&surface.veg_density, organism_query.iter());
+    check.max_drift = surface
+        .veg_density
+        .iter()
+        .zip(occupancy.iter())
+        .map(|(tracked, exact)| (tracked - exact).abs())
+        .fold(0.0, f32::max);
+    check.num_negative = surface
+        .veg_density
+        .iter()
+        .filter(|&&density| density < 0.0)
+        .count();
+    if check.max_drift > OCCUPANCY_TOLERANCE {
+        warn!(
+            "vegetation density drifted by up to {} with {} negative cells",
+            check.max_drift, check.num_negative
+        );
+    } else {
+        debug!(
+            "vegetation density drifted by up to {} with {} negative cells",
+            check.max_drift, check.num_negative
+        );
+    }
+    surface.veg_density.copy_from(&occupancy);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    const STEP: Duration = Duration::from_millis(100);
+
+    // World with a surface on which organisms grow at the full rate.
+    fn world() -> World {
+        let mut world = World::new();
+        let mut time = Time::<()>::default();
+        time.advance_by(STEP);
+        world.insert_resource(time);
+        let mut general_params = parameters::GeneralParameters::default();
+        general_params.calendar.seasonality = 0.0;
+        world.insert_resource(general_params);
+        world.insert_resource(calendar::Calendar::default());
+        world.init_resource::<Messages<OrganismBorn>>();
+        world.init_resource::<Messages<OrganismDied>>();
+        world.init_resource::<OccupancyCheck>();
+        world.init_resource::<OrganismIds>();
+
+        let mut surface = Surface::new(0);
+        surface.moisture.fill(1.0);
+        surface.temperature.fill(20.0);
+        world.spawn(surface);
+        world
+    }
+
+    fn veg_density(world: &mut World) -> Vec<f32> {
+        let mut query = world.query::<&Surface>();
+        query
+            .single(world)
+            .unwrap()
+            .veg_density
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    fn spawn_organisms(world: &mut World) {
+        world
+            .run_system_once(
+                |mut commands: Commands,
+                 mut ids: ResMut<OrganismIds>,
+                 mut surface_query: Query<&mut Surface>| {
+                    let mut surface = surface_query.single_mut().unwrap();
+                    // overlapping organisms of different ages and a single one
+                    let cluster = (0..25)
+                        .map(|i| vec2(10.0, 10.0) + 0.37 * vec2((i % 5) as f32, (i / 5) as f32));
+                    for (i, p) in cluster.chain([vec2(30.5, 20.5)]).enumerate() {
+                        let organism = Organism {
+                            age: 0.7 * i as f32,
+                            ..Organism::new(&mut ids)
+                        };
+                        spawn_copy(
+                            &mut commands,
+                            &mut ids,
+                            &mut surface,
+                            &crate::GrassAssets::default(),
+                            Transform::from_xyz(p.x, 0.0, p.y),
+                            &organism,
+                        );
+                    }
+                },
+            )
+            .unwrap();
+    }
+
+    fn num_organisms(world: &mut World) -> usize {
+        world.query::<&Organism>().iter(world).count()
+    }
+
+    // Run fixed steps until all organisms died.
+    fn run_until_extinct(world: &mut World) {
+        for _ in 0..10_000 {
+            world.run_system_once(update_organisms_system).unwrap();
+            world.run_system_once(occupancy_system).unwrap();
+            if num_organisms(world) == 0 {
+                return;
+            }
+        }
+        panic!("the organisms did not die");
+    }
+
+    fn assert_empty(world: &mut World) {
+        let veg_density = veg_density(world);
+        assert!(veg_density.iter().all(|&density| density >= 0.0));
+        assert!(veg_density.iter().all(|&density| density == 0.0));
+    }
+
+    fn grow(world: &mut World, steps: usize) {
+        for _ in 0..steps {
+            world.run_system_once(update_organisms_system).unwrap();
+            world.run_system_once(occupancy_system).unwrap();
+        }
+        assert!(veg_density(world).iter().any(|&density| density > 0.1));
+    }
+
+    #[test]
+    fn density_is_zero_after_death_by_age() {
+        let mut world = world();
+        world
+            .resource_mut::<parameters::GeneralParameters>()
+            .grass
+            .max_age = 25.0;
+        spawn_organisms(&mut world);
+        grow(&mut world, 20);
+        run_until_extinct(&mut world);
+        assert_empty(&mut world);
+    }
+
+    #[test]
+    fn density_is_zero_after_death_by_drought() {
+        let mut world = world();
+        world
+            .resource_mut::<parameters::GeneralParameters>()
+            .grass
+            .drought_tolerance = 5.0;
+        spawn_organisms(&mut world);
+        grow(&mut world, 20);
+        world
+            .run_system_once(|mut surface_query: Query<&mut Surface>| {
+                surface_query.single_mut().unwrap().moisture.fill(0.0);
+            })
+            .unwrap();
+        run_until_extinct(&mut world);
+        assert_empty(&mut world);
+    }
+
+    #[test]
+    fn removing_all_organisms_clears_the_density() {
+        let mut world = world();
+        spawn_organisms(&mut world);
+        grow(&mut world, 20);
+        world
+            .run_system_once(
+                |mut commands: Commands,
+                 mut surface_query: Query<&mut Surface>,
+                 organism_query: Query<(Entity, &Transform, &Organism)>| {
+                    let mut surface = surface_query.single_mut().unwrap();
+                    for (id, transform, organism) in organism_query.iter() {
+                        remove_organism(
+                            &mut commands,
+                            &mut surface,
+                            id,
+                            transform,
+                            organism,
+                            DeathCause::Removed,
+                        );
+                    }
+                },
+            )
+            .unwrap();
+        assert_eq!(num_organisms(&mut world), 0);
+        assert_empty(&mut world);
+    }
+
+    #[test]
+    fn rebuild_matches_tracked_density_after_growth() {
+        let mut world = world();
+        spawn_organisms(&mut world);
+        // grows the organisms without reaching a periodic rebuild
+        for _ in 0..20 {
+            world.run_system_once(update_organisms_system).unwrap();
+        }
+        let mut query = world.query::<(&Transform, &Organism)>();
+        let organisms: Vec<_> = query
+            .iter(&world)
+            .map(|(transform, organism)| (*transform, organism.clone()))
+            .collect();
+        assert!(organisms.iter().all(|(_, organism)| organism.size > 0.5));
+        let mut surface_query = world.query::<&Surface>();
+        let surface = surface_query.single(&world).unwrap();
+        let occupancy = rebuild_occupancy(
+            &surface.veg_density,
+            organisms
+                .iter()
+                .map(|(transform, organism)| (transform, organism)),
+        );
+        assert!(occupancy.iter().any(|&density| density > 0.1));
+        for (tracked, exact) in surface.veg_density.iter().zip(occupancy.iter()) {
+            assert!((tracked - exact).abs() < OCCUPANCY_TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn restored_ids_are_not_allocated_again() {
+        let mut ids = OrganismIds::default();
+        assert_eq!(ids.allocate(), OrganismId(0));
+        let mut restored = Organism::new(&mut OrganismIds { next: 5 });
+        ids.restore(&mut restored);
+        assert_eq!(restored.id(), OrganismId(5));
+        assert_eq!(ids.allocate(), OrganismId(6));
+
+        // organisms of snapshots without ids
+        let mut unassigned: Organism =
+            ron::from_str("(age: 1.0, size: 0.5, surface_area: 0.1, water_deficit: 0.0)").unwrap();
+        ids.restore(&mut unassigned);
+        assert_eq!(unassigned.id(), OrganismId(7));
+    }
+
+    #[test]
+    fn rebuild_without_organisms_is_empty() {
+        let mut veg_density = domain::Field::<f32>::new(0);
+        veg_density.fill(0.7);
+        let occupancy = rebuild_occupancy(&veg_density, std::iter::empty());
+        assert_eq!(occupancy.size, veg_density.size);
+        assert!(occupancy.iter().all(|&density| density == 0.0));
     }
 }
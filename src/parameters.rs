@@ -3,30 +3,143 @@ use bevy_egui::egui::Align2;
 use bevy_egui::{EguiContexts, egui};
 
 use egui_probe::{EguiProbe, Probe};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
 
+use crate::calendar;
+use crate::fire;
 use crate::grass;
+use crate::habitat;
+use crate::replay::Intervention;
+use crate::temperature;
+use crate::undo;
+use crate::weather;
 
-#[derive(EguiProbe)]
+#[derive(Clone, PartialEq, EguiProbe, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SunParameters {
     pub day_duration: f32,
     pub is_moving: bool,
+    // [deg], determines the seasonal variation of the sun
+    pub latitude: f32,
 }
 
 impl Default for SunParameters {
     fn default() -> Self {
-        SunParameters { day_duration : 120.0, is_moving : false }
+        SunParameters { day_duration : 120.0, is_moving : false, latitude : 45.0 }
     }
 }
 
-#[derive(Resource, EguiProbe, Default)]
+#[derive(Resource, Clone, PartialEq, EguiProbe, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GeneralParameters {
     pub sun : SunParameters,
+    pub calendar: calendar::CalendarParameters,
     pub grass: grass::GrassParameters,
+    pub weather: weather::WeatherParameters,
+    pub fire: fire::FireParameters,
+    pub temperature: temperature::TemperatureParameters,
+    pub habitat: habitat::HabitatParameters,
+}
+
+impl GeneralParameters {
+    // Load parameters from a RON file. Values missing in the file keep their defaults.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        ron::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, text)
+    }
+
+    fn to_tree(&self) -> Result<serde_json::Value, String> {
+        serde_json::to_value(self).map_err(|err| err.to_string())
+    }
+
+    // Parameter or group of parameters by its path, e.g. "grass" or "grass.max_age".
+    pub fn get_value(&self, name: &str) -> Result<serde_json::Value, String> {
+        self.to_tree()?
+            .pointer(&json_pointer(name))
+            .cloned()
+            .ok_or_else(|| format!("unknown parameter {name}"))
+    }
+
+    // Replace a parameter or group of parameters by its path.
+    pub fn set_value(&mut self, name: &str, value: serde_json::Value) -> Result<(), String> {
+        let mut tree = self.to_tree()?;
+        *tree
+            .pointer_mut(&json_pointer(name))
+            .ok_or_else(|| format!("unknown parameter {name}"))? = value;
+        *self = serde_json::from_value(tree).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    // Numeric parameter by its path, e.g. "grass.max_age".
+    pub fn get(&self, name: &str) -> Result<f64, String> {
+        self.get_value(name)?
+            .as_f64()
+            .ok_or_else(|| format!("parameter {name} is not a number"))
+    }
+
+    // Set a numeric parameter by its path, e.g. "grass.max_age". Integers are rounded.
+    pub fn set(&mut self, name: &str, value: f64) -> Result<(), String> {
+        let entry = self.get_value(name)?;
+        let value = if entry.is_u64() {
+            serde_json::Value::from(value.round().max(0.0) as u64)
+        } else if entry.is_f64() {
+            serde_json::Value::from(value)
+        } else {
+            return Err(format!("parameter {name} is not a number"));
+        };
+        self.set_value(name, value)
+    }
+
+    // Paths of the parameters which differ from the ones of other.
+    pub fn changed(&self, other: &GeneralParameters) -> Vec<String> {
+        let mut names = Vec::new();
+        if let (Ok(tree), Ok(other_tree)) = (self.to_tree(), other.to_tree()) {
+            changed_paths("", &tree, &other_tree, &mut names);
+        }
+        names
+    }
+}
+
+fn json_pointer(name: &str) -> String {
+    format!("/{}", name.replace('.', "/"))
+}
+
+// Collect the paths of the values which differ between two parameter trees. Objects with the
+// same keys are compared per value, anything else as a whole, e.g. an enum with another variant.
+fn changed_paths(
+    path: &str,
+    a: &serde_json::Value,
+    b: &serde_json::Value,
+    names: &mut Vec<String>,
+) {
+    match (a, b) {
+        (serde_json::Value::Object(a), serde_json::Value::Object(b))
+            if a.len() == b.len() && a.keys().all(|key| b.contains_key(key)) =>
+        {
+            for (key, value) in a {
+                let name = if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+                changed_paths(&name, value, &b[key], names);
+            }
+        }
+        _ if a != b => names.push(path.to_string()),
+        _ => {}
+    }
 }
 
 #[derive(Default)]
 pub struct ParameterUiConfig {
     is_visible: bool,
+    // parameters changed by the current edit, e.g. dragging a value, with their previous values
+    pending: Vec<(String, serde_json::Value)>,
 }
 
 pub fn parameter_ui_system(
@@ -34,21 +147,75 @@ pub fn parameter_ui_system(
     mut ui_config: Local<ParameterUiConfig>,
     key_input: Res<ButtonInput<KeyCode>>,
     mut general_params: ResMut<GeneralParameters>,
+    mut undo_history: ResMut<undo::UndoHistory>,
+    mut interventions: MessageWriter<Intervention>,
 ) -> Result {
     if key_input.just_pressed(KeyCode::F4) {
         ui_config.is_visible = !ui_config.is_visible;
     }
 
     if ui_config.is_visible {
+        let ctx = contexts.ctx_mut()?;
+        let previous = general_params.clone();
         egui::Window::new("Parameters")
             .default_open(true)
             .max_size([300.0, 200.0])
             .anchor(Align2::RIGHT_TOP, egui::vec2(5.0, 5.0))
             .vscroll(true)
-            .show(contexts.ctx_mut()?, |ui| {
+            .show(ctx, |ui| {
                 Probe::new(&mut *general_params).show(ui);
             });
+
+        // An edit is recorded as a single undo step once it is finished. Only the parameters
+        // changed in the window are recorded, not the ones changed by the simulation meanwhile.
+        let changed = previous.changed(&general_params);
+        for name in changed.iter() {
+            if !ui_config.pending.iter().any(|(pending, _)| pending == name)
+                && let Ok(value) = previous.get_value(name)
+            {
+                ui_config.pending.push((name.clone(), value));
+            }
+        }
+        if changed.is_empty()
+            && !ui_config.pending.is_empty()
+            && !ctx.input(|i| i.pointer.any_down())
+        {
+            let pending = std::mem::take(&mut ui_config.pending);
+            undo_history.record(undo::Action::SetParameters(pending));
+            interventions.write(Intervention::SetParameters);
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_parameters_are_listed_by_path() {
+        let before = GeneralParameters::default();
+        let mut after = before.clone();
+        after.grass.max_age += 1.0;
+        after.sun.is_moving = !after.sun.is_moving;
+        let mut changed = before.changed(&after);
+        changed.sort();
+        assert_eq!(changed, ["grass.max_age", "sun.is_moving"]);
+        assert!(before.changed(&before).is_empty());
+    }
+
+    #[test]
+    fn a_value_is_restored_without_the_other_changes() {
+        let before = GeneralParameters::default();
+        let mut params = before.clone();
+        params.grass.max_age += 1.0;
+        // e.g. changed by a script meanwhile
+        params.sun.latitude = 10.0;
+        let value = before.get_value("grass.max_age").unwrap();
+        params.set_value("grass.max_age", value).unwrap();
+        assert_eq!(params.grass.max_age, before.grass.max_age);
+        assert_eq!(params.sun.latitude, 10.0);
+        assert!(params.set_value("grass.unknown", 1.0.into()).is_err());
+    }
+}
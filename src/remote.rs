@@ -0,0 +1,279 @@
+//! Remote control over the Bevy Remote Protocol, a JSON-RPC interface enabled with
+//! `--remote <PORT>`. The server only listens on localhost. Besides the built-in `world.*`
+//! methods of Bevy, the following methods are available:
+//! - `eco.pause`, `eco.resume`
+//! - `eco.step` with `{"steps": n}` runs n fixed steps while paused, spread over several frames
+//! - `eco.get_parameter` with `{"name": "grass.max_age"}`
+//! - `eco.set_parameter` with `{"name": "grass.max_age", "value": 60.0}`
+//! - `eco.population` returns the number of organisms, the simulated time and the number of
+//!   requested steps which did not run yet
+//! - `eco.field` with `{"name": "moisture"}` returns the size and the values of a visualized
+//!   field, row by row
+//! - `eco.spawn` with `{"positions": [[x, y], ...]}` returns the ids of the new organisms, which
+//!   are the ones of the lineage and the event log
+//! - `eco.kill` with `{"ids": [...]}` removes the organisms with the given ids
+//!
+//! Example:
+//! `curl -X POST localhost:15702 -d '{"jsonrpc": "2.0", "id": 1, "method": "eco.population"}'`
+
+use bevy::prelude::*;
+use bevy::remote::http::RemoteHttpPlugin;
+use bevy::remote::{BrpError, BrpResult, RemotePlugin, error_codes};
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+
+use crate::domain;
+use crate::field_vis::{FieldVisRegistry, VisFieldKind};
+use crate::organism::{self, Organism, OrganismId};
+use crate::parameters::GeneralParameters;
+use crate::replay::Intervention;
+use crate::terrain::{Surface, Terrain};
+use crate::time_control::TimeControl;
+
+pub struct RemoteControlPlugin {
+    pub port: u16,
+}
+
+impl Plugin for RemoteControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(
+            RemotePlugin::default()
+                .with_method("eco.pause", pause_method)
+                .with_method("eco.resume", resume_method)
+                .with_method("eco.step", step_method)
+                .with_method("eco.get_parameter", get_parameter_method)
+                .with_method("eco.set_parameter", set_parameter_method)
+                .with_method("eco.population", population_method)
+                .with_method("eco.field", field_method)
+                .with_method("eco.spawn", spawn_method)
+                .with_method("eco.kill", kill_method),
+        )
+        .add_plugins(RemoteHttpPlugin::default().with_port(self.port));
+    }
+}
+
+fn invalid_params(message: impl ToString) -> BrpError {
+    BrpError {
+        code: error_codes::INVALID_PARAMS,
+        message: message.to_string(),
+        data: None,
+    }
+}
+
+// Missing parameters are treated like an empty object, so that fields with defaults can be
+// omitted.
+fn parse<T: DeserializeOwned>(params: Option<Value>) -> BrpResult<T> {
+    serde_json::from_value(params.unwrap_or_else(|| json!({}))).map_err(invalid_params)
+}
+
+fn pause_method(In(_): In<Option<Value>>, mut time: ResMut<Time<Virtual>>) -> BrpResult {
+    time.pause();
+    Ok(Value::Null)
+}
+
+fn resume_method(In(_): In<Option<Value>>, mut time: ResMut<Time<Virtual>>) -> BrpResult {
+    time.unpause();
+    Ok(Value::Null)
+}
+
+// steps of a single call, they run in batches over several frames
+const MAX_STEPS: u32 = 100_000;
+
+#[derive(Deserialize)]
+struct StepParams {
+    #[serde(default = "default_steps")]
+    steps: u32,
+}
+
+fn default_steps() -> u32 {
+    1
+}
+
+fn step_method(
+    In(params): In<Option<Value>>,
+    time: Res<Time<Virtual>>,
+    mut control: ResMut<TimeControl>,
+) -> BrpResult {
+    let params: StepParams = parse(params)?;
+    if !time.is_paused() {
+        return Err(invalid_params("the simulation has to be paused"));
+    }
+    if params.steps > MAX_STEPS {
+        return Err(invalid_params(format!(
+            "at most {MAX_STEPS} steps per call"
+        )));
+    }
+    control.request_steps(params.steps);
+    Ok(Value::Null)
+}
+
+#[derive(Deserialize)]
+struct ParameterParams {
+    name: String,
+    #[serde(default)]
+    value: Option<f64>,
+}
+
+fn get_parameter_method(
+    In(params): In<Option<Value>>,
+    general_params: Res<GeneralParameters>,
+) -> BrpResult {
+    let params: ParameterParams = parse(params)?;
+    let value = general_params.get(&params.name).map_err(invalid_params)?;
+    Ok(json!(value))
+}
+
+fn set_parameter_method(
+    In(params): In<Option<Value>>,
+    mut general_params: ResMut<GeneralParameters>,
+    mut interventions: MessageWriter<Intervention>,
+) -> BrpResult {
+    let params: ParameterParams = parse(params)?;
+    let value = params
+        .value
+        .ok_or_else(|| invalid_params("missing value"))?;
+    general_params
+        .set(&params.name, value)
+        .map_err(invalid_params)?;
+    interventions.write(Intervention::SetParameters);
+    Ok(Value::Null)
+}
+
+fn population_method(
+    In(_): In<Option<Value>>,
+    fixed_time: Res<Time<Fixed>>,
+    control: Res<TimeControl>,
+    organism_query: Query<(), With<Organism>>,
+) -> BrpResult {
+    Ok(json!({
+        "population": organism_query.iter().count(),
+        "time": fixed_time.elapsed_secs_f64(),
+        "pending_steps": control.pending_steps(&fixed_time),
+    }))
+}
+
+#[derive(Deserialize)]
+struct FieldParams {
+    name: String,
+}
+
+fn field_method(
+    In(params): In<Option<Value>>,
+    registry: Res<FieldVisRegistry>,
+    field_query: Query<(&Terrain, &Surface)>,
+) -> BrpResult {
+    let params: FieldParams = parse(params)?;
+    let vis_field = registry
+        .iter()
+        .find(|vis_field| vis_field.name == params.name)
+        .ok_or_else(|| invalid_params(format!("unknown field {}", params.name)))?;
+    let (terrain, surface) = field_query.single().map_err(BrpError::internal)?;
+    let (size, values) = match &vis_field.kind {
+        VisFieldKind::Scalar { field, .. } => {
+            let field = field(terrain, surface);
+            (field.size, json!(field.iter().collect::<Vec<_>>()))
+        }
+        VisFieldKind::Categorical { field, .. } => {
+            let field = field(terrain, surface);
+            (field.size, json!(field.iter().collect::<Vec<_>>()))
+        }
+    };
+    Ok(json!({
+        "size": size.to_array(),
+        "values": values,
+    }))
+}
+
+#[derive(Deserialize)]
+struct SpawnParams {
+    positions: Vec<[f32; 2]>,
+}
+
+fn spawn_method(
+    In(params): In<Option<Value>>,
+    mut commands: Commands,
+    terrain_query: Query<&Terrain>,
+    grass_assets: Res<crate::GrassAssets>,
+    general_params: Res<GeneralParameters>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+) -> BrpResult {
+    let params: SpawnParams = parse(params)?;
+    let positions: Vec<Vec2> = params.positions.into_iter().map(Vec2::from).collect();
+    if let Some(p) = positions.iter().find(|p| !domain::bounds().contains(**p)) {
+        return Err(invalid_params(format!(
+            "position {p} is outside of the domain"
+        )));
+    }
+
+    let terrain = terrain_query.single().map_err(BrpError::internal)?;
+    let ids: Vec<OrganismId> = positions
+        .into_iter()
+        .map(|p| {
+            let (_, id) = organism::spawn_seedling(
+                &mut commands,
+                &mut organism_ids,
+                &grass_assets,
+                terrain,
+                p,
+                None,
+                &general_params.grass,
+                &mut rng,
+            );
+            commands.write_message(Intervention::Plant {
+                position: p.to_array(),
+                count: 1,
+            });
+            id
+        })
+        .collect();
+    Ok(json!(ids))
+}
+
+#[derive(Deserialize)]
+struct KillParams {
+    ids: Vec<OrganismId>,
+}
+
+fn kill_method(
+    In(params): In<Option<Value>>,
+    mut commands: Commands,
+    mut surface_query: Query<&mut Surface>,
+    organism_query: Query<(Entity, &Transform, &Organism)>,
+) -> BrpResult {
+    let mut params: KillParams = parse(params)?;
+    params.ids.sort_unstable();
+    params.ids.dedup();
+    let organisms: Vec<_> = organism_query
+        .iter()
+        .filter(|(_, _, organism)| params.ids.binary_search(&organism.id()).is_ok())
+        .collect();
+    // all ids are checked first, so that nothing is removed on errors
+    let found: Vec<OrganismId> = organisms
+        .iter()
+        .map(|(_, _, organism)| organism.id())
+        .collect();
+    if let Some(id) = params.ids.iter().find(|id| !found.contains(id)) {
+        return Err(invalid_params(format!("organism {} does not exist", id.0)));
+    }
+
+    let mut surface = surface_query.single_mut().map_err(BrpError::internal)?;
+    for (id, transform, organism) in organisms {
+        organism::remove_organism(
+            &mut commands,
+            &mut surface,
+            id,
+            transform,
+            organism,
+            organism::DeathCause::Removed,
+        );
+        commands.write_message(Intervention::Remove {
+            position: transform.translation.xz().to_array(),
+            count: 1,
+        });
+    }
+    Ok(Value::Null)
+}
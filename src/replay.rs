@@ -0,0 +1,363 @@
+//! Event log of a run and its replay.
+//! Every birth, death and user intervention is recorded with the simulated time. F7 saves the
+//! log to the output directory, which also happens at the end of a run. `--replay <FILE>`
+//! regenerates the terrain of the recorded run and shows the population at any point in time
+//! without simulating, so runs can be reviewed and shared as a small file. Runs which started
+//! from a snapshot are shown on the generated terrain.
+
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+use bincode::Options as _;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cli::{self, Options};
+use crate::organism::{DeathCause, OrganismBorn, OrganismDied};
+use crate::parameters::GeneralParameters;
+use crate::terrain::Terrain;
+use crate::time_control::TimeControl;
+
+// Modifications of the simulation by the user, also through scenarios, scripts, the remote
+// interface and the gym.
+#[derive(Message, Serialize, Deserialize, Clone, Debug)]
+pub enum Intervention {
+    Plant { position: [f32; 2], count: usize },
+    Remove { position: [f32; 2], count: usize },
+    Ignite { position: [f32; 2] },
+    // a brush stroke starting at the position
+    EditField { field: String, position: [f32; 2] },
+    SetParameters,
+    Undo,
+    Redo,
+}
+
+impl Intervention {
+    fn description(&self) -> String {
+        match self {
+            Intervention::Plant { position, count } => {
+                format!("plant {count} at ({:.1}, {:.1})", position[0], position[1])
+            }
+            Intervention::Remove { position, count } => {
+                format!("remove {count} at ({:.1}, {:.1})", position[0], position[1])
+            }
+            Intervention::Ignite { position } => {
+                format!("ignite at ({:.1}, {:.1})", position[0], position[1])
+            }
+            Intervention::EditField { field, position } => {
+                format!("edit {field} at ({:.1}, {:.1})", position[0], position[1])
+            }
+            Intervention::SetParameters => "set parameters".to_string(),
+            Intervention::Undo => "undo".to_string(),
+            Intervention::Redo => "redo".to_string(),
+        }
+    }
+}
+
+// Organisms are identified by their persistent ids.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum LogEvent {
+    Birth {
+        id: u64,
+        parent: Option<u64>,
+        position: [f32; 2],
+    },
+    Death {
+        id: u64,
+        cause: DeathCause,
+        age: f32,
+    },
+    Intervention(Intervention),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    // simulated time [s]
+    pub time: f32,
+    pub event: LogEvent,
+}
+
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct EventLog {
+    // together with the world size this determines the terrain
+    pub seed: Option<u64>,
+    pub world_size: usize,
+    // ordered by time
+    pub entries: Vec<LogEntry>,
+}
+
+// Variable length integers keep the ids and counts small.
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_varint_encoding()
+}
+
+impl EventLog {
+    pub fn new(options: &Options) -> Self {
+        EventLog {
+            seed: options.seed,
+            world_size: options.world_size,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let log: EventLog = bincode_options()
+            .deserialize(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if !log.world_size.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the world size is not a power of two",
+            ));
+        }
+        Ok(log)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let bytes = bincode_options()
+            .serialize(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, bytes)
+    }
+
+    pub fn end_time(&self) -> f32 {
+        self.entries.last().map_or(0.0, |entry| entry.time)
+    }
+}
+
+pub struct EventLogPlugin;
+
+impl Plugin for EventLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<Intervention>()
+            .add_systems(
+                Last,
+                record_events_system.run_if(resource_exists::<EventLog>),
+            )
+            .add_systems(
+                Update,
+                save_event_log_system.run_if(resource_exists::<EventLog>),
+            );
+    }
+}
+
+// Messages are stamped with the time at the end of the frame, which is exact in headless runs
+// with one fixed step per update.
+pub fn record_events_system(
+    fixed_time: Res<Time<Fixed>>,
+    mut log: ResMut<EventLog>,
+    mut births: MessageReader<OrganismBorn>,
+    mut deaths: MessageReader<OrganismDied>,
+    mut interventions: MessageReader<Intervention>,
+) {
+    let time = fixed_time.elapsed_secs();
+    let births = births.read().map(|birth| LogEvent::Birth {
+        id: birth.id.0,
+        parent: birth.lineage.parent.map(|parent| parent.0),
+        position: birth.position.to_array(),
+    });
+    let deaths = deaths.read().map(|death| LogEvent::Death {
+        id: death.id.0,
+        cause: death.cause,
+        age: death.age,
+    });
+    let interventions = interventions
+        .read()
+        .map(|intervention| LogEvent::Intervention(intervention.clone()));
+    let events: Vec<LogEvent> = interventions.chain(births).chain(deaths).collect();
+    log.entries
+        .extend(events.into_iter().map(|event| LogEntry { time, event }));
+}
+
+pub fn save_event_log_system(
+    key_input: Res<ButtonInput<KeyCode>>,
+    options: Res<cli::Options>,
+    log: Res<EventLog>,
+) {
+    if !key_input.just_pressed(KeyCode::F7) {
+        return;
+    }
+
+    match options
+        .output_path(cli::EVENTS_FILE)
+        .and_then(|path| log.save(&path).map(|_| path))
+    {
+        Ok(path) => info!("event log written to {}", path.display()),
+        Err(err) => error!("failed to write event log: {}", err),
+    }
+}
+
+// Organisms are shown without simulating them, so they have a fixed size.
+const GHOST_SIZE: f32 = 0.5;
+const MAX_REPLAY_SPEED: f32 = 256.0;
+
+// Stand-in for a recorded organism.
+#[derive(Component)]
+pub struct Ghost;
+
+#[derive(Resource)]
+pub struct Replay {
+    log: EventLog,
+    // simulated time which is shown [s]
+    time: f32,
+    // number of entries which are applied to the ghosts
+    applied: usize,
+    ghosts: HashMap<u64, Entity>,
+    is_playing: bool,
+    speed: f32,
+}
+
+impl Replay {
+    pub fn new(log: EventLog) -> Self {
+        Replay {
+            log,
+            time: 0.0,
+            applied: 0,
+            ghosts: HashMap::new(),
+            is_playing: false,
+            speed: 8.0,
+        }
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        // the time control is replaced by the replay controls but still shown in the hud
+        app.init_resource::<TimeControl>()
+            .add_systems(Startup, pause_simulation_system)
+            .add_systems(Update, replay_system)
+            .add_systems(EguiPrimaryContextPass, replay_ui_system);
+    }
+}
+
+// The recorded run is shown instead of simulated.
+fn pause_simulation_system(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+// Advance the replay and update the ghosts incrementally. Going back in time rebuilds them from
+// the start of the log.
+pub fn replay_system(
+    mut commands: Commands,
+    real_time: Res<Time<Real>>,
+    mut replay: ResMut<Replay>,
+    terrain_query: Query<&Terrain>,
+    grass_assets: Res<crate::GrassAssets>,
+    general_params: Res<GeneralParameters>,
+) {
+    let Ok(terrain) = terrain_query.single() else {
+        return;
+    };
+    let replay = &mut *replay;
+    let end_time = replay.log.end_time();
+    if replay.is_playing {
+        replay.time += real_time.delta_secs() * replay.speed;
+        if replay.time >= end_time {
+            replay.time = end_time;
+            replay.is_playing = false;
+        }
+    }
+
+    let target = replay
+        .log
+        .entries
+        .partition_point(|entry| entry.time <= replay.time);
+    if target < replay.applied {
+        for (_, ghost) in replay.ghosts.drain() {
+            commands.entity(ghost).despawn();
+        }
+        replay.applied = 0;
+    }
+
+    for entry in &replay.log.entries[replay.applied..target] {
+        match &entry.event {
+            LogEvent::Birth { id, position, .. } => {
+                let p = Vec2::from_array(*position);
+                let height =
+                    terrain.height_map.get_bilinear(p) - general_params.grass.below_surface_depth;
+                let ghost = commands
+                    .spawn((
+                        Mesh3d(grass_assets.mesh.clone()),
+                        bevy::light::NotShadowCaster::default(),
+                        MeshMaterial3d(grass_assets.material.clone()),
+                        Transform::from_translation(Vec3::new(p.x, height, p.y))
+                            .with_scale(Vec3::splat(GHOST_SIZE))
+                            // varied but stable orientation
+                            .with_rotation(Quat::from_rotation_y(*id as f32)),
+                        Ghost,
+                    ))
+                    .id();
+                replay.ghosts.insert(*id, ghost);
+            }
+            LogEvent::Death { id, .. } => {
+                if let Some(ghost) = replay.ghosts.remove(id) {
+                    commands.entity(ghost).despawn();
+                }
+            }
+            LogEvent::Intervention(_) => {}
+        }
+    }
+    replay.applied = target;
+}
+
+pub fn replay_ui_system(mut contexts: EguiContexts, mut replay: ResMut<Replay>) -> Result {
+    let replay = &mut *replay;
+    let end_time = replay.log.end_time();
+    egui::Window::new("Replay")
+        .resizable(false)
+        .anchor(Align2::CENTER_BOTTOM, egui::vec2(0.0, -5.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            ui.horizontal(|ui| {
+                if ui
+                    .button(if replay.is_playing { "pause" } else { "play" })
+                    .clicked()
+                {
+                    // start over at the end of the log
+                    if !replay.is_playing && replay.time >= end_time {
+                        replay.time = 0.0;
+                    }
+                    replay.is_playing = !replay.is_playing;
+                }
+                ui.add(
+                    egui::Slider::new(&mut replay.speed, 1.0..=MAX_REPLAY_SPEED)
+                        .logarithmic(true)
+                        .text("speed"),
+                );
+            });
+            ui.add(egui::Slider::new(&mut replay.time, 0.0..=end_time).text("time [s]"));
+            ui.label(format!("population: {}", replay.ghosts.len()));
+
+            ui.separator();
+            ui.label("interventions");
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    let mut jump_to = None;
+                    for entry in &replay.log.entries {
+                        if let LogEvent::Intervention(intervention) = &entry.event
+                            && ui
+                                .button(format!(
+                                    "{:.1} s: {}",
+                                    entry.time,
+                                    intervention.description()
+                                ))
+                                .clicked()
+                        {
+                            jump_to = Some(entry.time);
+                        }
+                    }
+                    if let Some(time) = jump_to {
+                        replay.time = time;
+                        replay.is_playing = false;
+                    }
+                });
+        });
+
+    Ok(())
+}
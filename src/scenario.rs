@@ -0,0 +1,296 @@
+//! Scenarios of timed events, started with `--scenario scenario.ron` in the viewer or headless.
+//! Times are simulated seconds since the start of the run. Events with the same time are
+//! executed in the order of the file. Outputs are written to the output directory at the end of
+//! the step in which their event is due.
+//!
+//! Example of a scenario file:
+//! ```ron
+//! (
+//!     events: [
+//!         (time: 300.0, action: SetParameter(name: "grass.max_age", value: 60.0)),
+//!         (time: 300.0, action: Plant(count: 50, min: (10.0, 10.0), max: (20.0, 20.0))),
+//!         (time: 600.0, action: Drought(days: 30)),
+//!         (time: 900.0, action: Snapshot(file: "drought.ron")),
+//!         (time: 900.0, action: ExportFields(fields: ["moisture", "vegetation density"])),
+//!         (time: 1200.0, action: Stop),
+//!     ],
+//! )
+//! ```
+
+use bevy::prelude::*;
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+use rand::prelude::*;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::calendar;
+use crate::cli;
+use crate::domain;
+use crate::field_vis::{FieldVisRegistry, VisField, VisFieldKind};
+use crate::organism;
+use crate::parameters::GeneralParameters;
+use crate::replay::Intervention;
+use crate::snapshot::SimulationState;
+use crate::terrain::{Surface, Terrain};
+use crate::weather::Weather;
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Action {
+    // set a numeric parameter by its path in GeneralParameters, e.g. "grass.max_age"
+    SetParameter {
+        name: String,
+        value: f64,
+    },
+    // plant seedlings at uniformly distributed positions in the rectangle [min, max)
+    Plant {
+        count: usize,
+        min: (f32, f32),
+        max: (f32, f32),
+    },
+    Drought {
+        days: u32,
+    },
+    // save a snapshot under the given file name
+    Snapshot {
+        file: String,
+    },
+    // write fields as csv grids, all visualized fields if none are given
+    ExportFields {
+        #[serde(default)]
+        fields: Vec<String>,
+    },
+    // write the results and exit like at the end of --duration
+    Stop,
+}
+
+impl Action {
+    // Outputs are written at the end of the step, after the simulation has been updated.
+    fn is_output(&self) -> bool {
+        matches!(self, Action::Snapshot { .. } | Action::ExportFields { .. })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Event {
+    pub time: f64, // [s]
+    pub action: Action,
+}
+
+#[derive(Resource, Deserialize)]
+pub struct Scenario {
+    pub events: Vec<Event>,
+    // index of the next event which is not due yet
+    #[serde(skip)]
+    next: usize,
+    // due output events which are written at the end of the step
+    #[serde(skip)]
+    pending_outputs: Vec<Event>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut scenario: Scenario =
+            ron::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        scenario
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        // stable, so events with the same time keep their order
+        scenario.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(scenario)
+    }
+
+    // Report errors before the simulation starts instead of when an event is due.
+    fn validate(&self) -> Result<(), String> {
+        let mut params = GeneralParameters::default();
+        let vis_fields = crate::vis_fields();
+        for event in &self.events {
+            if !event.time.is_finite() || event.time < 0.0 {
+                return Err(format!("invalid event time {}", event.time));
+            }
+            match &event.action {
+                Action::SetParameter { name, value } => params.set(name, *value)?,
+                Action::ExportFields { fields } => {
+                    if let Some(name) = fields
+                        .iter()
+                        .find(|name| !vis_fields.iter().any(|vis_field| vis_field.name == *name))
+                    {
+                        return Err(format!("unknown field {name}"));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            scenario_system
+                .before(calendar::advance_calendar_system)
+                .run_if(resource_exists::<Scenario>),
+        )
+        .add_systems(
+            FixedLast,
+            scenario_output_system
+                .before(cli::end_of_run_system)
+                .run_if(resource_exists::<Scenario>),
+        );
+    }
+}
+
+// Execute the events which are due.
+fn scenario_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut scenario: ResMut<Scenario>,
+    mut general_params: ResMut<GeneralParameters>,
+    mut weather: ResMut<Weather>,
+    mut stop: ResMut<cli::StopRequest>,
+    terrain_query: Query<&Terrain>,
+    grass_assets: Res<crate::GrassAssets>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+) {
+    let elapsed = time.elapsed_secs_f64();
+    let scenario = &mut *scenario;
+    while let Some(event) = scenario.events.get(scenario.next) {
+        if event.time > elapsed {
+            break;
+        }
+        scenario.next += 1;
+        info!("scenario event at {}s: {:?}", event.time, event.action);
+
+        match &event.action {
+            Action::SetParameter { name, value } => match general_params.set(name, *value) {
+                Ok(()) => {
+                    commands.write_message(Intervention::SetParameters);
+                }
+                Err(err) => error!("{}", err),
+            },
+            Action::Plant { count, min, max } => {
+                let area = Rect::from_corners(Vec2::from(*min), Vec2::from(*max))
+                    .intersect(domain::bounds());
+                if area.is_empty() {
+                    warn!("plant region is outside of the domain");
+                    continue;
+                }
+                let terrain = terrain_query.single().unwrap();
+                for _ in 0..*count {
+                    let p = area.min + Vec2::new(rng.random(), rng.random()) * area.size();
+                    organism::spawn_seedling(
+                        &mut commands,
+                        &mut organism_ids,
+                        &grass_assets,
+                        terrain,
+                        p,
+                        None,
+                        &general_params.grass,
+                        &mut rng,
+                    );
+                }
+                commands.write_message(Intervention::Plant {
+                    position: area.center().to_array(),
+                    count: *count,
+                });
+            }
+            Action::Drought { days } => weather.start_drought(*days),
+            Action::Stop => stop.is_requested = true,
+            Action::Snapshot { .. } | Action::ExportFields { .. } => {}
+        }
+        if event.action.is_output() {
+            scenario.pending_outputs.push(event.clone());
+        }
+    }
+}
+
+fn export_field(
+    vis_field: &VisField,
+    terrain: &Terrain,
+    surface: &Surface,
+    path: &Path,
+) -> io::Result<()> {
+    match &vis_field.kind {
+        VisFieldKind::Scalar { field, .. } => field(terrain, surface).write_csv(path),
+        VisFieldKind::Categorical { field, .. } => field(terrain, surface).write_csv(path),
+    }
+}
+
+fn scenario_output_system(
+    mut scenario: ResMut<Scenario>,
+    options: Res<cli::Options>,
+    state: SimulationState,
+    field_query: Query<(&Terrain, &Surface)>,
+    registry: Res<FieldVisRegistry>,
+) {
+    for event in scenario.pending_outputs.drain(..) {
+        let result = match &event.action {
+            Action::Snapshot { file } => options
+                .output_path(file)
+                .and_then(|path| state.snapshot().save(&path)),
+            // the field names were validated when the scenario was loaded
+            Action::ExportFields { fields } => {
+                let (terrain, surface) = field_query.single().unwrap();
+                registry
+                    .iter()
+                    .filter(|vis_field| {
+                        fields.is_empty() || fields.iter().any(|name| name == vis_field.name)
+                    })
+                    .try_for_each(|vis_field| {
+                        let file =
+                            format!("{}_{}.csv", vis_field.name.replace(' ', "_"), event.time);
+                        options
+                            .output_path(&file)
+                            .and_then(|path| export_field(vis_field, terrain, surface, &path))
+                    })
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            error!(
+                "failed to write scenario output at {}s: {}",
+                event.time, err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(text: &str) -> Result<(), String> {
+        ron::from_str::<Scenario>(text).unwrap().validate()
+    }
+
+    #[test]
+    fn valid_scenario() {
+        let text = r#"(events: [
+            (time: 10.0, action: SetParameter(name: "grass.max_age", value: 30.0)),
+            (time: 20.0, action: ExportFields(fields: ["moisture", "vegetation density"])),
+            (time: 30.0, action: ExportFields()),
+            (time: 40.0, action: Stop),
+        ])"#;
+        assert_eq!(validate(text), Ok(()));
+    }
+
+    #[test]
+    fn invalid_events_are_rejected() {
+        for action in [
+            r#"SetParameter(name: "grass.unknown", value: 1.0)"#,
+            r#"ExportFields(fields: ["moisture", "humidity"])"#,
+        ] {
+            let text = format!("(events: [(time: 1.0, action: {action})])");
+            assert!(validate(&text).is_err(), "{action}");
+        }
+        assert!(validate("(events: [(time: -1.0, action: Stop)])").is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! Custom interventions in Rhai scripts, started with `--script script.rhai`.
+//! A script defines callbacks which are executed during the fixed update:
+//! `on_step()` after each simulation step and `on_day(day)` at the start of each simulated day.
+//! Both are optional. `this` is an object map which keeps its values between calls, also when
+//! the script is reloaded after it was modified on disk. A callback which takes too many
+//! operations, e.g. in an infinite loop, is stopped with an error.
+//!
+//! Available functions, positions are in domain coordinates:
+//! - `time()`, `day()`, `domain_size()`, `random()` in [0,1)
+//! - `field(name, x, y)` value of a visualized field, e.g. "moisture"
+//! - `set_field(name, x, y, value)` for "height", "moisture" and "nutrients"
+//! - `slope(x, y)` [°]
+//! - `organisms()` array of maps with id, x, y, age and size, the id is the one of the lineage
+//!   and the event log
+//! - `remove(id)`, `plant(x, y)`
+//! - `parameter(name)`, `set_parameter(name, value)` with paths like "grass.max_age"
+//!
+//! Example:
+//! ```rhai
+//! // every 10 days remove 20% of the organisms on slopes steeper than 30°
+//! fn on_day(day) {
+//!     if day % 10 != 0 {
+//!         return;
+//!     }
+//!     for o in organisms() {
+//!         if slope(o.x, o.y) > 30.0 && random() < 0.2 {
+//!             remove(o.id);
+//!         }
+//!     }
+//! }
+//! ```
+
+use bevy::prelude::*;
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use rhai::{AST, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::calendar::{self, Calendar};
+use crate::domain;
+use crate::field_vis::{CategoricalFieldFn, FieldVisRegistry, ScalarFieldFn, VisFieldKind};
+use crate::organism::{self, Organism, OrganismId};
+use crate::parameters::GeneralParameters;
+use crate::replay::Intervention;
+use crate::terrain::{Surface, Terrain};
+use crate::undo::EditableField;
+
+// real time between checks for modifications of the script file [s]
+const RELOAD_INTERVAL: f32 = 0.5;
+// operations after which a callback is stopped, e.g. in an infinite loop
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+#[derive(Clone, Copy)]
+enum FieldFn {
+    Scalar(ScalarFieldFn),
+    Categorical(CategoricalFieldFn),
+}
+
+// The part of the simulation which is accessible from a script.
+// Filled before and applied after each call of a callback.
+#[derive(Default)]
+struct ScriptContext {
+    time: f64,
+    day: u32,
+    terrain: Terrain,
+    surface: Surface,
+    fields: Vec<(&'static str, FieldFn)>,
+    organisms: Vec<(Vec2, Organism)>,
+    parameters: GeneralParameters,
+    is_parameters_changed: bool,
+    removed: Vec<OrganismId>,
+    planted: Vec<Vec2>,
+    rng: Option<StdRng>,
+}
+
+impl ScriptContext {
+    fn field(&self, name: &str, p: Vec2) -> ScriptResult<f64> {
+        let (_, field) = self
+            .fields
+            .iter()
+            .find(|(field_name, _)| *field_name == name)
+            .ok_or_else(|| format!("unknown field {name}"))?;
+        Ok(match field {
+            FieldFn::Scalar(field) => field(&self.terrain, &self.surface).get_bilinear(p) as f64,
+            FieldFn::Categorical(field) => {
+                field(&self.terrain, &self.surface).get_nearest(p) as f64
+            }
+        })
+    }
+
+    fn set_field(&mut self, name: &str, p: Vec2, value: f64) -> ScriptResult<()> {
+        let field = EditableField::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+            .ok_or_else(|| format!("field {name} can not be modified"))?;
+        let field = field.get_mut(&mut self.terrain, &mut self.surface);
+        let idx = field.nearest_index(p);
+        field[idx.to_array()] = value as f32;
+        Ok(())
+    }
+
+    fn slope(&self, p: Vec2) -> f64 {
+        let height_map = &self.terrain.height_map;
+        let gradient = height_map.gradient(height_map.nearest_index(p));
+        gradient.length().atan().to_degrees() as f64
+    }
+
+    fn organisms(&self) -> rhai::Array {
+        self.organisms
+            .iter()
+            .map(|(p, organism)| {
+                let mut map = Map::new();
+                map.insert("id".into(), (organism.id().0 as i64).into());
+                map.insert("x".into(), (p.x as f64).into());
+                map.insert("y".into(), (p.y as f64).into());
+                map.insert("age".into(), (organism.age() as f64).into());
+                map.insert("size".into(), (organism.size() as f64).into());
+                map.into()
+            })
+            .collect()
+    }
+}
+
+#[derive(Resource)]
+pub struct Script {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    engine: Engine,
+    ast: AST,
+    // `this` of the callbacks
+    state: Dynamic,
+    context: Arc<Mutex<ScriptContext>>,
+    last_day: Option<u32>,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn position(x: f64, y: f64) -> Vec2 {
+    Vec2::new(x as f32, y as f32)
+}
+
+// Engine with the functions which access the context.
+fn create_engine(context: &Arc<Mutex<ScriptContext>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let ctx = context.clone();
+    engine.register_fn("time", move || ctx.lock().unwrap().time);
+    let ctx = context.clone();
+    engine.register_fn("day", move || ctx.lock().unwrap().day as i64);
+    engine.register_fn("domain_size", || domain::size_f32().x as f64);
+    let ctx = context.clone();
+    engine.register_fn("random", move || {
+        let mut ctx = ctx.lock().unwrap();
+        ctx.rng.as_mut().map_or(0.0, |rng| rng.random::<f64>())
+    });
+    let ctx = context.clone();
+    engine.register_fn("field", move |name: &str, x: f64, y: f64| {
+        ctx.lock().unwrap().field(name, position(x, y))
+    });
+    let ctx = context.clone();
+    engine.register_fn(
+        "set_field",
+        move |name: &str, x: f64, y: f64, value: f64| {
+            ctx.lock().unwrap().set_field(name, position(x, y), value)
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("slope", move |x: f64, y: f64| {
+        ctx.lock().unwrap().slope(position(x, y))
+    });
+    let ctx = context.clone();
+    engine.register_fn("organisms", move || ctx.lock().unwrap().organisms());
+    let ctx = context.clone();
+    engine.register_fn("remove", move |id: i64| -> ScriptResult<()> {
+        let id = u64::try_from(id).map_err(|_| "invalid organism id")?;
+        ctx.lock().unwrap().removed.push(OrganismId(id));
+        Ok(())
+    });
+    let ctx = context.clone();
+    engine.register_fn("plant", move |x: f64, y: f64| {
+        ctx.lock().unwrap().planted.push(position(x, y));
+    });
+    let ctx = context.clone();
+    engine.register_fn("parameter", move |name: &str| -> ScriptResult<f64> {
+        Ok(ctx.lock().unwrap().parameters.get(name)?)
+    });
+    let ctx = context.clone();
+    engine.register_fn(
+        "set_parameter",
+        move |name: &str, value: f64| -> ScriptResult<()> {
+            let mut ctx = ctx.lock().unwrap();
+            ctx.parameters.set(name, value)?;
+            ctx.is_parameters_changed = true;
+            Ok(())
+        },
+    );
+    engine
+}
+
+impl Script {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let context = Arc::new(Mutex::new(ScriptContext::default()));
+        let engine = create_engine(&context);
+        let modified = modified_time(path);
+        let ast = compile(&engine, path)?;
+        Ok(Script {
+            path: path.to_path_buf(),
+            modified,
+            engine,
+            ast,
+            state: Map::new().into(),
+            context,
+            last_day: None,
+        })
+    }
+
+    fn has_callback(&self, name: &str, num_params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == num_params)
+    }
+
+    fn call(&mut self, name: &str, args: impl FuncArgs) {
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            name,
+            args,
+        );
+        if let Err(err) = result {
+            error!("{} in {}: {}", name, self.path.display(), err);
+        }
+    }
+}
+
+fn compile(engine: &Engine, path: &Path) -> io::Result<AST> {
+    engine
+        .compile_file(path.to_path_buf())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+pub struct ScriptPlugin;
+
+impl Plugin for ScriptPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            script_system
+                .after(calendar::advance_calendar_system)
+                .before(organism::update_organisms_system)
+                .run_if(resource_exists::<Script>),
+        )
+        .add_systems(
+            Update,
+            reload_script_system.run_if(resource_exists::<Script>),
+        );
+    }
+}
+
+// Recompile the script when the file was modified. The old version is kept on errors.
+fn reload_script_system(time: Res<Time<Real>>, mut script: ResMut<Script>, mut timer: Local<f32>) {
+    *timer += time.delta_secs();
+    if *timer < RELOAD_INTERVAL {
+        return;
+    }
+    *timer = 0.0;
+
+    let modified = modified_time(&script.path);
+    if modified.is_none() || modified == script.modified {
+        return;
+    }
+    script.modified = modified;
+    match compile(&script.engine, &script.path) {
+        Ok(ast) => {
+            script.ast = ast;
+            info!("reloaded script {}", script.path.display());
+        }
+        Err(err) => error!("failed to reload script {}: {}", script.path.display(), err),
+    }
+}
+
+fn script_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    calendar: Res<Calendar>,
+    mut script: ResMut<Script>,
+    mut general_params: ResMut<GeneralParameters>,
+    mut field_query: Query<(&mut Terrain, &mut Surface)>,
+    organism_query: Query<(Entity, &Transform, &Organism)>,
+    registry: Res<FieldVisRegistry>,
+    grass_assets: Res<crate::GrassAssets>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+) {
+    let day = calendar.day();
+    let is_new_day = script.last_day != Some(day);
+    script.last_day = Some(day);
+    let has_on_step = script.has_callback("on_step", 0);
+    let has_on_day = is_new_day && script.has_callback("on_day", 1);
+    if !has_on_step && !has_on_day {
+        return;
+    }
+
+    // move the state into the context for the duration of the calls
+    let (mut terrain, mut surface) = field_query.single_mut().unwrap();
+    {
+        let mut ctx = script.context.lock().unwrap();
+        ctx.time = time.elapsed_secs_f64();
+        ctx.day = day;
+        ctx.terrain = std::mem::take(&mut *terrain);
+        ctx.surface = std::mem::take(&mut *surface);
+        ctx.fields = registry
+            .iter()
+            .map(|vis_field| {
+                let field = match &vis_field.kind {
+                    VisFieldKind::Scalar { field, .. } => FieldFn::Scalar(*field),
+                    VisFieldKind::Categorical { field, .. } => FieldFn::Categorical(*field),
+                };
+                (vis_field.name, field)
+            })
+            .collect();
+        ctx.organisms = organism_query
+            .iter()
+            .map(|(_, transform, organism)| (transform.translation.xz(), organism.clone()))
+            .collect();
+        ctx.parameters = general_params.clone();
+        ctx.is_parameters_changed = false;
+        ctx.rng = Some(StdRng::seed_from_u64(rng.random()));
+    }
+
+    if has_on_day {
+        script.call("on_day", (day as i64,));
+    }
+    if has_on_step {
+        script.call("on_step", ());
+    }
+
+    let mut ctx = script.context.lock().unwrap();
+    *terrain = std::mem::take(&mut ctx.terrain);
+    *surface = std::mem::take(&mut ctx.surface);
+    ctx.organisms.clear();
+    if ctx.is_parameters_changed {
+        *general_params = ctx.parameters.clone();
+        commands.write_message(Intervention::SetParameters);
+    }
+
+    let mut removed = std::mem::take(&mut ctx.removed);
+    removed.sort_unstable();
+    removed.dedup();
+    // ids of organisms which do not exist anymore are ignored
+    for (id, transform, organism) in organism_query
+        .iter()
+        .filter(|(_, _, organism)| removed.binary_search(&organism.id()).is_ok())
+    {
+        organism::remove_organism(
+            &mut commands,
+            &mut surface,
+            id,
+            transform,
+            organism,
+            organism::DeathCause::Removed,
+        );
+        commands.write_message(Intervention::Remove {
+            position: transform.translation.xz().to_array(),
+            count: 1,
+        });
+    }
+    for p in ctx.planted.drain(..) {
+        if domain::bounds().contains(p) {
+            organism::spawn_seedling(
+                &mut commands,
+                &mut organism_ids,
+                &grass_assets,
+                &terrain,
+                p,
+                None,
+                &general_params.grass,
+                &mut rng,
+            );
+            commands.write_message(Intervention::Plant {
+                position: p.to_array(),
+                count: 1,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infinite_loops_are_stopped() {
+        let path = std::env::temp_dir().join(format!("eco-sim-loop-{}.rhai", std::process::id()));
+        fs::write(
+            &path,
+            "fn on_step() { this.count = 0; loop { this.count += 1; } }",
+        )
+        .unwrap();
+        let mut script = Script::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        script.call("on_step", ());
+        let count = script.state.as_map_ref().unwrap()["count"]
+            .as_int()
+            .unwrap();
+        assert!(count > 0 && (count as u64) < MAX_OPERATIONS);
+    }
+}
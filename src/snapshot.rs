@@ -0,0 +1,224 @@
+//! Snapshots of the simulation state in RON format.
+//! F6 saves a snapshot to the output directory, `--save-snapshot` also at the end of a run, and
+//! `--snapshot` starts a run from one.
+//! The state of the random number generator is not included, so a continued run diverges from
+//! the original one.
+
+use bevy::ecs::system::SystemParam;
+use bevy::math::USizeVec2;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::calendar::Calendar;
+use crate::cli;
+use crate::domain::{self, Field, Region};
+use crate::fire::FireState;
+use crate::organism::{self, Organism};
+use crate::parameters::GeneralParameters;
+use crate::terrain::{self, Surface, Terrain};
+use crate::weather::Weather;
+
+#[derive(Serialize, Deserialize)]
+struct OrganismState {
+    translation: [f32; 3],
+    rotation: [f32; 4],
+    scale: [f32; 3],
+    organism: Organism,
+}
+
+// A snapshot loaded from the command line, which replaces the generated world at startup.
+#[derive(Serialize, Deserialize, Resource)]
+pub struct Snapshot {
+    field_size: [usize; 2],
+    parameters: GeneralParameters,
+    calendar: Calendar,
+    weather: Weather,
+    height_map: Vec<f32>,
+    veg_density: Vec<f32>,
+    moisture: Vec<f32>,
+    nutrients: Vec<f32>,
+    burn_time: Vec<f32>,
+    temperature: Vec<f32>,
+    habitat: Vec<u8>,
+    organisms: Vec<OrganismState>,
+}
+
+fn read_field<T: Default + Copy + num_traits::NumAssign>(field: &Field<T>) -> Vec<T> {
+    field.iter().copied().collect()
+}
+
+fn write_field<T: Default + Copy + num_traits::NumAssign>(field: &mut Field<T>, values: &[T]) {
+    let region = Region {
+        min: USizeVec2::ZERO,
+        max: field.size,
+    };
+    field.write_region(region, values);
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let snapshot: Snapshot =
+            ron::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let num_elem = snapshot.field_size[0] * snapshot.field_size[1];
+        let is_consistent = [
+            &snapshot.height_map,
+            &snapshot.veg_density,
+            &snapshot.moisture,
+            &snapshot.nutrients,
+            &snapshot.burn_time,
+            &snapshot.temperature,
+        ]
+        .iter()
+        .all(|values| values.len() == num_elem)
+            && snapshot.habitat.len() == num_elem;
+        if !is_consistent {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "field sizes do not match",
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    // Load a snapshot to start a run from, which has to match the world size of the run.
+    pub fn load_for_run(path: &Path) -> Result<Self, String> {
+        let snapshot = Snapshot::load(path)
+            .map_err(|err| format!("failed to load snapshot {}: {err}", path.display()))?;
+        let field_size = domain::size() * (1 << terrain::SUBDIVISIONS);
+        if snapshot.field_size != field_size.to_array() {
+            return Err(format!(
+                "snapshot {} was saved with a different world size",
+                path.display()
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text =
+            ron::to_string(self).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, text)
+    }
+}
+
+// Everything which is stored in a snapshot.
+#[derive(SystemParam)]
+pub struct SimulationState<'w, 's> {
+    field_query: Query<'w, 's, (&'static Terrain, &'static Surface)>,
+    organism_query: Query<'w, 's, (&'static Transform, &'static Organism)>,
+    calendar: Res<'w, Calendar>,
+    weather: Res<'w, Weather>,
+    general_params: Res<'w, GeneralParameters>,
+}
+
+impl SimulationState<'_, '_> {
+    pub fn parameters(&self) -> &GeneralParameters {
+        &self.general_params
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let (terrain, surface) = self.field_query.single().unwrap();
+        Snapshot {
+            field_size: terrain.height_map.size.to_array(),
+            parameters: self.general_params.clone(),
+            calendar: self.calendar.clone(),
+            weather: self.weather.clone(),
+            height_map: read_field(&terrain.height_map),
+            veg_density: read_field(&surface.veg_density),
+            moisture: read_field(&surface.moisture),
+            nutrients: read_field(&surface.nutrients),
+            burn_time: read_field(&surface.burn_time),
+            temperature: read_field(&surface.temperature),
+            habitat: read_field(&surface.habitat),
+            organisms: self
+                .organism_query
+                .iter()
+                .map(|(transform, organism)| OrganismState {
+                    translation: transform.translation.to_array(),
+                    rotation: transform.rotation.to_array(),
+                    scale: transform.scale.to_array(),
+                    organism: organism.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn save_snapshot_system(
+    key_input: Res<ButtonInput<KeyCode>>,
+    options: Res<cli::Options>,
+    state: SimulationState,
+) {
+    if !key_input.just_pressed(KeyCode::F6) {
+        return;
+    }
+
+    match options
+        .output_path(cli::SNAPSHOT_FILE)
+        .and_then(|path| state.snapshot().save(&path).map(|_| path))
+    {
+        Ok(path) => info!("snapshot written to {}", path.display()),
+        Err(err) => error!("failed to write snapshot: {}", err),
+    }
+}
+
+// Replace the generated world with the snapshot given on the command line, which was loaded
+// and validated when the app was built.
+// Parameters from a config file take precedence over the ones in the snapshot.
+pub fn load_snapshot_system(
+    mut commands: Commands,
+    options: Res<cli::Options>,
+    mut field_query: Query<(&mut Terrain, &mut Surface)>,
+    grass_assets: Res<crate::GrassAssets>,
+    mut calendar: ResMut<Calendar>,
+    mut weather: ResMut<Weather>,
+    mut fire: ResMut<FireState>,
+    mut general_params: ResMut<GeneralParameters>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+    snapshot: Option<Res<Snapshot>>,
+) {
+    let Some(snapshot) = snapshot else {
+        return;
+    };
+    let (mut terrain, mut surface) = field_query.single_mut().unwrap();
+    for state in &snapshot.organisms {
+        let transform = Transform {
+            translation: Vec3::from_array(state.translation),
+            rotation: Quat::from_array(state.rotation),
+            scale: Vec3::from_array(state.scale),
+        };
+        organism::spawn_copy(
+            &mut commands,
+            &mut organism_ids,
+            &mut surface,
+            &grass_assets,
+            transform,
+            &state.organism,
+        );
+    }
+
+    // after spawning, which adds the organisms to the vegetation density again
+    write_field(&mut terrain.height_map, &snapshot.height_map);
+    write_field(&mut surface.veg_density, &snapshot.veg_density);
+    write_field(&mut surface.moisture, &snapshot.moisture);
+    write_field(&mut surface.nutrients, &snapshot.nutrients);
+    write_field(&mut surface.burn_time, &snapshot.burn_time);
+    write_field(&mut surface.temperature, &snapshot.temperature);
+    write_field(&mut surface.habitat, &snapshot.habitat);
+    *fire = FireState::from_surface(&surface);
+    *calendar = snapshot.calendar.clone();
+    *weather = snapshot.weather.clone();
+    if options.config.is_none() {
+        *general_params = snapshot.parameters.clone();
+    }
+    commands.remove_resource::<Snapshot>();
+
+    if let Some(path) = &options.snapshot {
+        info!("loaded snapshot {}", path.display());
+    }
+}
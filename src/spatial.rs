@@ -0,0 +1,637 @@
+//! Statistics of the spatial pattern of the population: Ripley's K and L function, the pair
+//! correlation function and the nearest neighbour distances of the organisms, patches of covered
+//! cells and the radially averaged power spectrum of the vegetation density.
+//! F9 shows them as plots. The full curves are written next to the metrics with F5 and at the
+//! end of a run.
+
+use bevy::math::{FloatPow, USizeVec2, usizevec2};
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::domain::{self, Field};
+use crate::metrics::COVER_THRESHOLD;
+use crate::organism::Organism;
+use crate::terrain::Surface;
+
+const NUM_BINS: usize = 32;
+// largest distance of the distance based statistics relative to the side length of the domain
+const MAX_RADIUS: f32 = 0.25;
+// real time between two updates of the plots [s]
+const PLOT_INTERVAL: f32 = 1.0;
+const PLOT_SIZE: egui::Vec2 = egui::vec2(260.0, 90.0);
+
+pub struct SpatialStatistics {
+    // outer radius of each distance bin
+    pub radii: Vec<f32>,
+    pub ripley_k: Vec<f32>,
+    // sqrt(K / pi), equal to the radius for complete spatial randomness
+    pub ripley_l: Vec<f32>,
+    // in each distance bin, 1 for complete spatial randomness
+    pub pair_correlation: Vec<f32>,
+    // histogram of the nearest neighbour distances with the distance bins
+    pub nearest_neighbours: Vec<usize>,
+    pub mean_nearest_neighbour: Option<f32>,
+    // areas of the patches in descending order
+    pub patch_areas: Vec<f32>,
+    // cycles per unit length
+    pub wave_numbers: Vec<f32>,
+    pub power_spectrum: Vec<f32>,
+}
+
+impl SpatialStatistics {
+    pub fn new(positions: Vec<Vec2>, veg_density: &Field<f32>) -> Self {
+        let bin_width = MAX_RADIUS * domain::size_f32().min_element() / NUM_BINS as f32;
+        let radii: Vec<f32> = (1..=NUM_BINS).map(|i| i as f32 * bin_width).collect();
+        let ripley_k = ripley_k(&positions, bin_width);
+        let ripley_l = ripley_k.iter().map(|k| (k / PI).sqrt()).collect();
+        let pair_correlation = ripley_k
+            .iter()
+            .enumerate()
+            .map(|(i, k)| {
+                let inner = if i > 0 { ripley_k[i - 1] } else { 0.0 };
+                let inner_radius = i as f32 * bin_width;
+                (k - inner) / (PI * (radii[i].squared() - inner_radius.squared()))
+            })
+            .collect();
+
+        let distances = nearest_neighbour_distances(positions);
+        let mut nearest_neighbours = vec![0; NUM_BINS];
+        for d in &distances {
+            let bin = (d / bin_width) as usize;
+            if bin < NUM_BINS {
+                nearest_neighbours[bin] += 1;
+            }
+        }
+        let mean_nearest_neighbour =
+            (!distances.is_empty()).then(|| distances.iter().sum::<f32>() / distances.len() as f32);
+
+        let (wave_numbers, power_spectrum) = radial_power_spectrum(veg_density);
+        SpatialStatistics {
+            radii,
+            ripley_k,
+            ripley_l,
+            pair_correlation,
+            nearest_neighbours,
+            mean_nearest_neighbour,
+            patch_areas: patch_areas(veg_density),
+            wave_numbers,
+            power_spectrum,
+        }
+    }
+
+    // One value per line in the columns statistic, x and value. x is the radius of the distance
+    // bins, the rank of the patches or the wave number.
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "statistic,x,value")?;
+        for (i, r) in self.radii.iter().enumerate() {
+            writeln!(writer, "K,{},{}", r, self.ripley_k[i])?;
+            writeln!(writer, "L,{},{}", r, self.ripley_l[i])?;
+            writeln!(writer, "g,{},{}", r, self.pair_correlation[i])?;
+            writeln!(
+                writer,
+                "nearest_neighbours,{},{}",
+                r, self.nearest_neighbours[i]
+            )?;
+        }
+        for (i, area) in self.patch_areas.iter().enumerate() {
+            writeln!(writer, "patch_area,{},{}", i, area)?;
+        }
+        for (k, power) in self.wave_numbers.iter().zip(&self.power_spectrum) {
+            writeln!(writer, "power_spectrum,{},{}", k, power)?;
+        }
+        writer.flush()
+    }
+}
+
+// Distance of each position to its closest neighbour, empty for less than two positions.
+pub fn nearest_neighbour_distances(mut positions: Vec<Vec2>) -> Vec<f32> {
+    if positions.len() < 2 {
+        return Vec::new();
+    }
+    positions.sort_by(|a, b| a.x.total_cmp(&b.x));
+
+    // sweep along x, neighbours further away in x than the closest one found can be skipped
+    let mut distances = Vec::with_capacity(positions.len());
+    for (i, p) in positions.iter().enumerate() {
+        let mut min_sq = f32::INFINITY;
+        for q in positions[i + 1..].iter() {
+            if (q.x - p.x).squared() >= min_sq {
+                break;
+            }
+            min_sq = min_sq.min(p.distance_squared(*q));
+        }
+        for q in positions[..i].iter().rev() {
+            if (p.x - q.x).squared() >= min_sq {
+                break;
+            }
+            min_sq = min_sq.min(p.distance_squared(*q));
+        }
+        distances.push(min_sq.sqrt());
+    }
+    distances
+}
+
+// K at the outer radius of each bin. Pairs are weighted with the translation edge correction,
+// the inverse of the fraction of the domain in which their offset is observable.
+fn ripley_k(positions: &[Vec2], bin_width: f32) -> Vec<f32> {
+    let mut k = vec![0.0; NUM_BINS];
+    let n = positions.len();
+    if n < 2 {
+        return k;
+    }
+    let size = domain::size_f32();
+    let area = size.element_product();
+    let max_radius = bin_width * NUM_BINS as f32;
+
+    let mut sorted = positions.to_vec();
+    sorted.sort_by(|a, b| a.x.total_cmp(&b.x));
+    for (i, p) in sorted.iter().enumerate() {
+        for q in sorted[i + 1..].iter() {
+            if q.x - p.x >= max_radius {
+                break;
+            }
+            let offset = (*q - *p).abs();
+            let bin = (offset.length() / bin_width) as usize;
+            if bin < NUM_BINS {
+                // both orders of the pair
+                k[bin] += 2.0 * area / (size - offset).element_product();
+            }
+        }
+    }
+
+    let scale = area / (n * (n - 1)) as f32;
+    let mut sum = 0.0;
+    for value in k.iter_mut() {
+        sum += *value;
+        *value = sum * scale;
+    }
+    k
+}
+
+// Areas of the 4-connected regions of covered cells.
+pub fn patch_areas(veg_density: &Field<f32>) -> Vec<f32> {
+    let size = veg_density.size;
+    let is_covered = |idx: USizeVec2| veg_density[idx.to_array()] > COVER_THRESHOLD;
+    let mut is_visited = vec![false; veg_density.num_elem()];
+    let cell_area = 1.0 / veg_density.idx_scale.squared();
+
+    let mut areas = Vec::new();
+    let mut stack = Vec::new();
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let start = usizevec2(x, y);
+            if is_visited[x + y * size.x] || !is_covered(start) {
+                continue;
+            }
+            is_visited[x + y * size.x] = true;
+            stack.push(start);
+            let mut num_cells = 0;
+            while let Some(idx) = stack.pop() {
+                num_cells += 1;
+                let neighbours = [
+                    idx.x.checked_sub(1).map(|x| usizevec2(x, idx.y)),
+                    idx.y.checked_sub(1).map(|y| usizevec2(idx.x, y)),
+                    (idx.x + 1 < size.x).then(|| usizevec2(idx.x + 1, idx.y)),
+                    (idx.y + 1 < size.y).then(|| usizevec2(idx.x, idx.y + 1)),
+                ];
+                for neighbour in neighbours.into_iter().flatten() {
+                    let flat = neighbour.x + neighbour.y * size.x;
+                    if !is_visited[flat] && is_covered(neighbour) {
+                        is_visited[flat] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+            areas.push(num_cells as f32 * cell_area);
+        }
+    }
+    areas.sort_by(|a, b| b.total_cmp(a));
+    areas
+}
+
+// In-place radix-2 FFT of complex numbers stored as (re, im). The length is a power of two.
+fn fft(data: &mut [Vec2]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                // rotate is the complex multiplication
+                let w = Vec2::from_angle(angle * k as f32);
+                let a = data[start + k];
+                let b = w.rotate(data[start + k + len / 2]);
+                data[start + k] = a + b;
+                data[start + k + len / 2] = a - b;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Mean power of the fluctuations around the mean density in rings of the frequency plane,
+// from the lowest nonzero frequency up to the Nyquist frequency.
+fn radial_power_spectrum(field: &Field<f32>) -> (Vec<f32>, Vec<f32>) {
+    let size = field.size;
+    let mean = field.iter().sum::<f32>() / field.num_elem() as f32;
+    let mut data: Vec<Vec2> = field.iter().map(|v| Vec2::new(v - mean, 0.0)).collect();
+    for row in data.chunks_exact_mut(size.x) {
+        fft(row);
+    }
+    let mut column = vec![Vec2::ZERO; size.y];
+    for x in 0..size.x {
+        for (y, value) in column.iter_mut().enumerate() {
+            *value = data[x + y * size.x];
+        }
+        fft(&mut column);
+        for (y, value) in column.iter().enumerate() {
+            data[x + y * size.x] = *value;
+        }
+    }
+
+    // frequencies in cycles per unit length
+    let length = size.as_vec2() / field.idx_scale;
+    let bin_width = 1.0 / length.max_element();
+    let num_bins = size.min_element() / 2;
+    let mut power = vec![0.0; num_bins];
+    let mut counts = vec![0usize; num_bins];
+    let signed = |k: usize, n: usize| {
+        if k < n / 2 {
+            k as f32
+        } else {
+            k as f32 - n as f32
+        }
+    };
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let frequency = Vec2::new(signed(x, size.x), signed(y, size.y)) / length;
+            let bin = (frequency.length() / bin_width).round() as usize;
+            if (1..=num_bins).contains(&bin) {
+                power[bin - 1] += data[x + y * size.x].length_squared();
+                counts[bin - 1] += 1;
+            }
+        }
+    }
+
+    let num_elem = field.num_elem() as f32;
+    let wave_numbers = (1..=num_bins).map(|i| i as f32 * bin_width).collect();
+    let power = power
+        .iter()
+        .zip(&counts)
+        .map(|(p, &count)| {
+            if count > 0 {
+                p / count as f32 / num_elem
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    (wave_numbers, power)
+}
+
+#[derive(Resource, Default)]
+pub struct SpatialPlots {
+    is_visible: bool,
+    statistics: Option<SpatialStatistics>,
+    // real time until the next update [s]
+    next_update: f32,
+}
+
+pub struct SpatialStatisticsPlugin;
+
+impl Plugin for SpatialStatisticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialPlots>()
+            .add_systems(Update, update_spatial_plots_system)
+            .add_systems(EguiPrimaryContextPass, spatial_plots_ui_system);
+    }
+}
+
+pub fn update_spatial_plots_system(
+    key_input: Res<ButtonInput<KeyCode>>,
+    real_time: Res<Time<Real>>,
+    mut plots: ResMut<SpatialPlots>,
+    organism_query: Query<&Transform, With<Organism>>,
+    surface_query: Query<&Surface>,
+) {
+    if key_input.just_pressed(KeyCode::F9) {
+        plots.is_visible = !plots.is_visible;
+        plots.next_update = 0.0;
+    }
+    if !plots.is_visible {
+        return;
+    }
+    plots.next_update -= real_time.delta_secs();
+    if plots.next_update > 0.0 {
+        return;
+    }
+    plots.next_update = PLOT_INTERVAL;
+
+    let Ok(surface) = surface_query.single() else {
+        return;
+    };
+    let positions = organism_query
+        .iter()
+        .map(|transform| transform.translation.xz())
+        .collect();
+    plots.statistics = Some(SpatialStatistics::new(positions, &surface.veg_density));
+}
+
+// Line plot with the range of the values as labels.
+fn line_plot(ui: &mut egui::Ui, title: &str, xs: &[f32], ys: &[f32], reference: Option<f32>) {
+    ui.label(title);
+    let (rect, _) = ui.allocate_exact_size(PLOT_SIZE, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let text_color = ui.visuals().text_color();
+    painter.rect_stroke(
+        rect,
+        0.0,
+        egui::Stroke::new(1.0, ui.visuals().weak_text_color()),
+        egui::StrokeKind::Inside,
+    );
+
+    let values = ys
+        .iter()
+        .copied()
+        .chain(reference)
+        .filter(|y| y.is_finite());
+    let min = values.clone().fold(f32::INFINITY, f32::min);
+    let max = values.fold(f32::NEG_INFINITY, f32::max);
+    let (Some(&x_min), Some(&x_max)) = (xs.first(), xs.last()) else {
+        return;
+    };
+    if !min.is_finite() || x_max <= x_min {
+        return;
+    }
+    let range = (max - min).max(f32::EPSILON);
+    let to_screen = |x: f32, y: f32| {
+        egui::pos2(
+            rect.left() + (x - x_min) / (x_max - x_min) * rect.width(),
+            rect.bottom() - (y - min) / range * rect.height(),
+        )
+    };
+
+    if let Some(y) = reference {
+        painter.line_segment(
+            [to_screen(x_min, y), to_screen(x_max, y)],
+            egui::Stroke::new(1.0, ui.visuals().weak_text_color()),
+        );
+    }
+    let points = xs
+        .iter()
+        .zip(ys)
+        .filter(|(_, y)| y.is_finite())
+        .map(|(&x, &y)| to_screen(x, y))
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.5, egui::Color32::LIGHT_GREEN),
+    ));
+    for (y, align) in [(max, Align2::LEFT_TOP), (min, Align2::LEFT_BOTTOM)] {
+        painter.text(
+            to_screen(x_min, y) + egui::vec2(2.0, 0.0),
+            align,
+            format!("{y:.3}"),
+            egui::FontId::proportional(11.0),
+            text_color,
+        );
+    }
+    painter.text(
+        rect.right_bottom() - egui::vec2(2.0, 0.0),
+        Align2::RIGHT_BOTTOM,
+        format!("{x_max:.2}"),
+        egui::FontId::proportional(11.0),
+        text_color,
+    );
+}
+
+pub fn spatial_plots_ui_system(mut contexts: EguiContexts, plots: Res<SpatialPlots>) -> Result {
+    if !plots.is_visible {
+        return Ok(());
+    }
+
+    egui::Window::new("Spatial statistics")
+        .resizable(false)
+        .anchor(Align2::LEFT_TOP, egui::vec2(5.0, 40.0))
+        .vscroll(true)
+        .show(contexts.ctx_mut()?, |ui| {
+            let Some(stats) = &plots.statistics else {
+                return;
+            };
+            let l_minus_r: Vec<f32> = stats
+                .ripley_l
+                .iter()
+                .zip(&stats.radii)
+                .map(|(l, r)| l - r)
+                .collect();
+            line_plot(ui, "L(r) - r", &stats.radii, &l_minus_r, Some(0.0));
+            line_plot(
+                ui,
+                "pair correlation g(r)",
+                &stats.radii,
+                &stats.pair_correlation,
+                Some(1.0),
+            );
+            let counts: Vec<f32> = stats
+                .nearest_neighbours
+                .iter()
+                .map(|&count| count as f32)
+                .collect();
+            line_plot(
+                ui,
+                "nearest neighbour distances",
+                &stats.radii,
+                &counts,
+                None,
+            );
+            let log_power: Vec<f32> = stats
+                .power_spectrum
+                .iter()
+                .map(|p| p.max(f32::MIN_POSITIVE).log10())
+                .collect();
+            line_plot(
+                ui,
+                "log10 power spectrum of the vegetation density",
+                &stats.wave_numbers,
+                &log_power,
+                None,
+            );
+
+            if let Some(mean) = stats.mean_nearest_neighbour {
+                ui.label(format!("mean nearest neighbour distance: {mean:.3}"));
+            }
+            ui.label(format!(
+                "patches: {}, largest: {:.1}",
+                stats.patch_areas.len(),
+                stats.patch_areas.first().copied().unwrap_or(0.0)
+            ));
+        });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+
+    fn random_positions(rng: &mut StdRng, n: usize) -> Vec<Vec2> {
+        let size = domain::size_f32();
+        (0..n)
+            .map(|_| Vec2::new(rng.random(), rng.random()) * size)
+            .collect()
+    }
+
+    fn ripley_l(positions: &[Vec2]) -> (Vec<f32>, Vec<f32>) {
+        let bin_width = MAX_RADIUS * domain::size_f32().min_element() / NUM_BINS as f32;
+        let radii = (1..=NUM_BINS).map(|i| i as f32 * bin_width).collect();
+        let l = ripley_k(positions, bin_width)
+            .iter()
+            .map(|k| (k / PI).sqrt())
+            .collect();
+        (radii, l)
+    }
+
+    #[test]
+    fn fft_of_a_sinusoid_peaks_at_its_frequency() {
+        let n = 64;
+        let cycles = 5;
+        let mut data: Vec<Vec2> = (0..n)
+            .map(|i| {
+                let phase = 2.0 * PI * (cycles * i) as f32 / n as f32;
+                Vec2::new(phase.cos(), 0.0)
+            })
+            .collect();
+        fft(&mut data);
+        for (k, value) in data.iter().enumerate() {
+            let expected = if k == cycles || k == n - cycles {
+                n as f32 / 2.0
+            } else {
+                0.0
+            };
+            assert!((value.length() - expected).abs() < 1e-3, "bin {k}: {value}");
+        }
+    }
+
+    #[test]
+    fn power_spectrum_peaks_at_the_wave_number_of_stripes() {
+        let mut field = Field::<f32>::new(0);
+        let cycles = 8;
+        for y in 0..field.size.y {
+            for x in 0..field.size.x {
+                let phase = 2.0 * PI * (cycles * x) as f32 / field.size.x as f32;
+                field[[x, y]] = 0.5 + 0.5 * phase.sin();
+            }
+        }
+        let (wave_numbers, power) = radial_power_spectrum(&field);
+        let peak = (0..power.len())
+            .max_by(|&a, &b| power[a].total_cmp(&power[b]))
+            .unwrap();
+        let expected = cycles as f32 / field.size.x as f32;
+        assert!((wave_numbers[peak] - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nearest_neighbours_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for n in [0, 1, 2, 10, 500] {
+            let positions = random_positions(&mut rng, n);
+            let mut expected: Vec<f32> = if n < 2 {
+                Vec::new()
+            } else {
+                positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        positions
+                            .iter()
+                            .enumerate()
+                            .filter(|(j, _)| *j != i)
+                            .map(|(_, q)| p.distance(*q))
+                            .fold(f32::INFINITY, f32::min)
+                    })
+                    .collect()
+            };
+            let mut distances = nearest_neighbour_distances(positions);
+            expected.sort_by(f32::total_cmp);
+            distances.sort_by(f32::total_cmp);
+            assert_eq!(distances, expected);
+        }
+    }
+
+    #[test]
+    fn patches_are_four_connected() {
+        let mut field = Field::<f32>::new(0);
+        // rectangle of 3 x 2 cells
+        for y in 2..4 {
+            for x in 2..5 {
+                field[[x, y]] = 1.0;
+            }
+        }
+        // L shape of 3 cells at the border
+        for idx in [[0, 10], [0, 11], [1, 11]] {
+            field[idx] = 1.0;
+        }
+        // diagonal neighbours are separate patches
+        field[[20, 20]] = 1.0;
+        field[[21, 21]] = 1.0;
+        // below the threshold
+        field[[30, 30]] = COVER_THRESHOLD;
+        assert_eq!(patch_areas(&field), [6.0, 3.0, 1.0, 1.0]);
+
+        // the cell area depends on the resolution
+        let mut field = Field::<f32>::new(1);
+        field[[7, 7]] = 1.0;
+        assert_eq!(patch_areas(&field), [0.25]);
+    }
+
+    #[test]
+    fn ripley_l_separates_lattice_and_clusters() {
+        // lattice with a spacing of 4, no pairs are closer
+        let spacing = 4.0;
+        let num_points = (domain::size_f32().x / spacing) as usize;
+        let lattice: Vec<Vec2> = (0..num_points * num_points)
+            .map(|i| (Vec2::new((i % num_points) as f32, (i / num_points) as f32) + 0.5) * spacing)
+            .collect();
+        let (radii, l) = ripley_l(&lattice);
+        for (r, l) in radii.iter().zip(&l) {
+            if *r <= spacing {
+                assert_eq!(*l, 0.0, "r = {r}");
+            }
+        }
+
+        // the same number of points in clusters with a radius of 1
+        let mut rng = StdRng::seed_from_u64(5);
+        let centers = random_positions(&mut rng, 16);
+        let clustered: Vec<Vec2> = (0..lattice.len())
+            .map(|i| centers[i % centers.len()] + Circle::new(1.0).sample_interior(&mut rng))
+            .filter(|p| domain::bounds().contains(*p))
+            .collect();
+        let (radii, l) = ripley_l(&clustered);
+        for (r, l) in radii.iter().zip(&l) {
+            if *r <= 2.0 {
+                assert!(*l > 2.0 * r, "r = {r}: L = {l}");
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Parameter sweeps over many headless runs, started with `--sweep sweep.ron`.
+//! Each run is a separate process of this executable with its own directory in the output
+//! directory. The summaries of all runs are collected in sweep.csv with one row per run.
+//!
+//! Example of a sweep file:
+//! ```ron
+//! (
+//!     parameters: [
+//!         (name: "grass.spawn_radius", min: 0.5, max: 2.0, steps: 4),
+//!         (name: "grass.max_age", min: 30.0, max: 120.0, steps: 4),
+//!     ],
+//!     sampling: Grid, // or Random(samples: 16, seed: 0)
+//!     replicates: 3,
+//!     duration: 3600.0,
+//! )
+//! ```
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::cli;
+use crate::metrics::RunSummary;
+use crate::parameters::GeneralParameters;
+
+const SWEEP_FILE: &str = "sweep.csv";
+// parameters of a single run, written before it is started
+const CONFIG_FILE: &str = "config.ron";
+const LOG_FILE: &str = "log.txt";
+
+#[derive(Deserialize)]
+pub struct ParameterRange {
+    // path of the parameter in GeneralParameters, e.g. "grass.max_age"
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+    // number of values on the grid
+    #[serde(default = "default_steps")]
+    pub steps: usize,
+}
+
+fn default_steps() -> usize {
+    5
+}
+
+#[derive(Deserialize, Default)]
+pub enum Sampling {
+    // all combinations of evenly spaced values
+    #[default]
+    Grid,
+    // independent and uniformly distributed values
+    Random {
+        samples: usize,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct Sweep {
+    // parameters which are not varied, otherwise the ones given with --config
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+    pub parameters: Vec<ParameterRange>,
+    #[serde(default)]
+    pub sampling: Sampling,
+    // each sample is run with the seeds 0..replicates
+    #[serde(default = "default_replicates")]
+    pub replicates: u64,
+    // simulated time of each run [s]
+    pub duration: f64,
+    // number of parallel runs, all available cores if none is given
+    #[serde(default)]
+    pub jobs: Option<usize>,
+}
+
+fn default_replicates() -> u64 {
+    1
+}
+
+impl Sweep {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let text = fs::read_to_string(path)?;
+        let sweep: Sweep = ron::from_str(&text).map_err(|err| invalid(err.to_string()))?;
+        if sweep.jobs == Some(0) {
+            return Err(invalid("jobs has to be at least 1".to_string()));
+        }
+        for range in &sweep.parameters {
+            if !range.min.is_finite() || !range.max.is_finite() {
+                return Err(invalid(format!(
+                    "{}: the bounds have to be finite",
+                    range.name
+                )));
+            }
+            if range.min > range.max {
+                return Err(invalid(format!("{}: min is larger than max", range.name)));
+            }
+        }
+        Ok(sweep)
+    }
+
+    // Parameter values of each sample in the order of self.parameters.
+    fn samples(&self) -> Vec<Vec<f64>> {
+        match self.sampling {
+            Sampling::Grid => {
+                let mut samples = vec![Vec::new()];
+                for range in &self.parameters {
+                    let steps = range.steps.max(1);
+                    let step = if steps > 1 {
+                        (range.max - range.min) / (steps - 1) as f64
+                    } else {
+                        0.0
+                    };
+                    samples = samples
+                        .iter()
+                        .flat_map(|sample| {
+                            (0..steps).map(move |i| {
+                                let mut sample = sample.clone();
+                                sample.push(range.min + i as f64 * step);
+                                sample
+                            })
+                        })
+                        .collect();
+                }
+                samples
+            }
+            Sampling::Random { samples, seed } => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                (0..samples)
+                    .map(|_| {
+                        self.parameters
+                            .iter()
+                            .map(|range| rng.random_range(range.min..=range.max))
+                            .collect()
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// Copy of base with the named parameters set to values.
+fn with_values(
+    base: &GeneralParameters,
+    ranges: &[ParameterRange],
+    values: &[f64],
+) -> Result<GeneralParameters, String> {
+    let mut params = base.clone();
+    for (range, &value) in ranges.iter().zip(values) {
+        params.set(&range.name, value)?;
+    }
+    Ok(params)
+}
+
+struct Run {
+    dir: PathBuf,
+    seed: u64,
+    values: Vec<f64>,
+}
+
+// Start a run as a child process and wait for its summary.
+fn execute(run: &Run, sweep: &Sweep, options: &cli::Options) -> io::Result<RunSummary> {
+    let log = File::create(run.dir.join(LOG_FILE))?;
+    let mut command = Command::new(std::env::current_exe()?);
+    if let Some(scenario) = &options.scenario {
+        command.arg("--scenario").arg(scenario);
+    }
+    if let Some(script) = &options.script {
+        command.arg("--script").arg(script);
+    }
+    if options.is_snapshot_saved {
+        command.arg("--save-snapshot");
+    }
+    let status = command
+        .arg("--headless")
+        .args(["--seed", &run.seed.to_string()])
+        .arg("--config")
+        .arg(run.dir.join(CONFIG_FILE))
+        .args(["--duration", &sweep.duration.to_string()])
+        .arg("--output")
+        .arg(&run.dir)
+        .args(["--world-size", &options.world_size.to_string()])
+        .args(["--fixed-hz", &options.fixed_hz.to_string()])
+        // runs are parallelized instead of the systems within a run
+        .args(["--threads", &options.threads.unwrap_or(1).to_string()])
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("run failed with {status}")));
+    }
+    RunSummary::load(&run.dir.join(cli::SUMMARY_FILE))
+}
+
+fn write_csv(
+    path: &Path,
+    sweep: &Sweep,
+    runs: &[Run],
+    summaries: &[Option<RunSummary>],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "run,seed")?;
+    for range in &sweep.parameters {
+        write!(writer, ",{}", range.name)?;
+    }
+    writeln!(
+        writer,
+        ",final_population,equilibrium_population,equilibrium_time,cover,mean_density,aggregation"
+    )?;
+
+    let optional = |value: Option<f32>| value.map_or(String::new(), |v| v.to_string());
+    for (idx, (run, summary)) in runs.iter().zip(summaries).enumerate() {
+        write!(writer, "{},{}", idx, run.seed)?;
+        for value in &run.values {
+            write!(writer, ",{}", value)?;
+        }
+        // failed runs have empty results
+        match summary {
+            Some(s) => writeln!(
+                writer,
+                ",{},{},{},{},{},{}",
+                s.final_population,
+                s.equilibrium_population,
+                optional(s.equilibrium_time),
+                s.cover,
+                s.mean_density,
+                optional(s.aggregation)
+            )?,
+            None => writeln!(writer, ",,,,,,")?,
+        }
+    }
+    writer.flush()
+}
+
+pub fn run_sweep(path: &Path, options: &cli::Options) -> Result<(), String> {
+    let sweep =
+        Sweep::load(path).map_err(|err| format!("failed to load {}: {err}", path.display()))?;
+    let base = match sweep.config.as_ref().or(options.config.as_ref()) {
+        Some(config) => GeneralParameters::load(config)
+            .map_err(|err| format!("failed to load {}: {err}", config.display()))?,
+        None => GeneralParameters::default(),
+    };
+
+    // prepare all runs first so that invalid parameters are reported before anything runs
+    let mut runs = Vec::new();
+    for values in sweep.samples() {
+        let params = with_values(&base, &sweep.parameters, &values)?;
+        for seed in 0..sweep.replicates {
+            let dir = options.output_dir.join(format!("run_{:04}", runs.len()));
+            fs::create_dir_all(&dir)
+                .and_then(|_| params.save(&dir.join(CONFIG_FILE)))
+                .map_err(|err| format!("failed to prepare {}: {err}", dir.display()))?;
+            runs.push(Run {
+                dir,
+                seed,
+                values: values.clone(),
+            });
+        }
+    }
+
+    let jobs = sweep
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    println!("running {} simulations with {} jobs", runs.len(), jobs);
+
+    let summaries: Mutex<Vec<Option<RunSummary>>> =
+        Mutex::new((0..runs.len()).map(|_| None).collect());
+    let next_run = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(runs.len()) {
+            scope.spawn(|| {
+                loop {
+                    let idx = next_run.fetch_add(1, Ordering::Relaxed);
+                    let Some(run) = runs.get(idx) else {
+                        break;
+                    };
+                    match execute(run, &sweep, options) {
+                        Ok(summary) => {
+                            println!("{} finished", run.dir.display());
+                            summaries.lock().unwrap()[idx] = Some(summary);
+                        }
+                        Err(err) => eprintln!("{}: {err}", run.dir.display()),
+                    }
+                }
+            });
+        }
+    });
+
+    let summaries = summaries.into_inner().unwrap();
+    let csv_path = options.output_dir.join(SWEEP_FILE);
+    write_csv(&csv_path, &sweep, &runs, &summaries)
+        .map_err(|err| format!("failed to write {}: {err}", csv_path.display()))?;
+    println!("results written to {}", csv_path.display());
+    Ok(())
+}
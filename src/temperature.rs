@@ -0,0 +1,76 @@
+use bevy::math::usizevec2;
+use bevy::prelude::*;
+
+use crate::calendar;
+use crate::parameters;
+use crate::terrain::{Surface, Terrain};
+use crate::weather::Weather;
+
+// simulated time between two updates of the temperature field [s]
+const UPDATE_INTERVAL: f32 = 1.0;
+
+#[derive(Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TemperatureParameters {
+    pub lapse_rate: f32, // [°C/km]
+    // real elevation of one unit of terrain height [m]
+    pub elevation_scale: f32,
+    // warming of a surface facing the sun [°C]
+    pub insolation_warming: f32,
+    // cooling while the sun is below the horizon [°C]
+    pub night_cooling: f32,
+}
+
+impl Default for TemperatureParameters {
+    fn default() -> Self {
+        TemperatureParameters {
+            lapse_rate: 6.5,
+            elevation_scale: 400.0,
+            insolation_warming: 8.0,
+            night_cooling: 4.0,
+        }
+    }
+}
+
+// Compute the local temperature from the daily mean temperature of the weather,
+// the elevation and the current insolation.
+pub fn temperature_system(
+    mut field_query: Query<(&Terrain, &mut Surface)>,
+    weather: Res<Weather>,
+    calendar: Res<calendar::Calendar>,
+    general_params: Res<parameters::GeneralParameters>,
+    time: Res<Time>,
+    mut next_update: Local<f32>,
+) {
+    if time.elapsed_secs() < *next_update {
+        return;
+    }
+    *next_update = time.elapsed_secs() + UPDATE_INTERVAL;
+
+    let params = &general_params.temperature;
+    let (terrain, mut surface) = field_query.single_mut().unwrap();
+    let sun_dir = calendar.sun_direction(&general_params.sun);
+    let is_night = sun_dir.y <= 0.0;
+    let base = if is_night {
+        weather.temperature - params.night_cooling
+    } else {
+        weather.temperature
+    };
+    let lapse = params.lapse_rate * params.elevation_scale / 1000.0;
+
+    let height_map = &terrain.height_map;
+    let size_x = surface.temperature.size.x;
+    for (i, temperature) in surface.temperature.iter_mut().enumerate() {
+        // both fields have the same resolution
+        let idx = usizevec2(i % size_x, i / size_x);
+        let gradient = height_map.gradient(idx);
+        let normal = Vec3::new(-gradient.x, 1.0, -gradient.y).normalize();
+        let insolation = if is_night {
+            0.0
+        } else {
+            normal.dot(sun_dir).max(0.0)
+        };
+        *temperature =
+            base - lapse * height_map[[idx.x, idx.y]] + params.insolation_warming * insolation;
+    }
+}
@@ -3,15 +3,16 @@ use bevy::color::{Color, Mix};
 use bevy::image::{
     Image, ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor,
 };
+use bevy::math::USizeVec2;
 use bevy::mesh::VertexAttributeValues;
 use bevy::prelude::*;
 use bevy::render::render_resource::{Extent3d, Face, TextureDimension, TextureFormat};
 use bevy::tasks::{ComputeTaskPool, ParallelSliceMut};
 
-use crate::{color_map, domain};
+use crate::{cli, color_map, domain};
 use noise::utils::{NoiseMap, NoiseMapBuilder};
 
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct Terrain {
     pub height_map: domain::Field<f32>,
 }
@@ -23,9 +24,57 @@ pub struct TerrainAssets {
     pub field_vis_image: Handle<Image>,
 }
 
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct Surface {
     pub veg_density: domain::Field<f32>,
+    // relative soil water content in [0,1]
+    pub moisture: domain::Field<f32>,
+    // additional nutrients, e.g. from ash
+    pub nutrients: domain::Field<f32>,
+    // remaining burn time of each cell [s], 0 if not burning
+    pub burn_time: domain::Field<f32>,
+    // [°C]
+    pub temperature: domain::Field<f32>,
+    // habitat::Habitat id of each cell
+    pub habitat: domain::Field<u8>,
+}
+
+// initial relative soil water content
+const INITIAL_MOISTURE: f32 = 0.5;
+// number of subdivisions of the fields per unit of the domain
+pub const SUBDIVISIONS: i32 = 3;
+
+impl Surface {
+    pub fn new(subdivisions: i32) -> Self {
+        let mut moisture = domain::Field::new(subdivisions);
+        moisture.fill(INITIAL_MOISTURE);
+        Surface {
+            veg_density: domain::Field::new(subdivisions),
+            moisture,
+            nutrients: domain::Field::new(subdivisions),
+            burn_time: domain::Field::new(subdivisions),
+            temperature: domain::Field::new(subdivisions),
+            habitat: domain::Field::new(subdivisions),
+        }
+    }
+
+    // Reset the changed regions of all fields.
+    pub fn clear_dirty(&mut self) {
+        self.veg_density.clear_dirty();
+        self.moisture.clear_dirty();
+        self.nutrients.clear_dirty();
+        self.burn_time.clear_dirty();
+        self.temperature.clear_dirty();
+        self.habitat.clear_dirty();
+    }
+}
+
+// Reset the changed regions of all fields at the end of a frame after all consumers have seen them.
+pub fn clear_dirty_system(mut field_query: Query<(&mut Terrain, &mut Surface)>) {
+    for (mut terrain, mut surface) in field_query.iter_mut() {
+        terrain.height_map.clear_dirty();
+        surface.clear_dirty();
+    }
 }
 
 fn get_terrain_height(noise_map: &NoiseMap, x: usize, y: usize) -> f32 {
@@ -38,16 +87,16 @@ const BOUNDARY_WIDTH: f32 = 0.1;
 const BOUNDARY_POS: f32 = -0.2;
 
 impl Terrain {
-    pub fn new(subdivisions: i32) -> Self {
+    pub fn new(subdivisions: i32, seed: u32) -> Self {
         let mut height_map = domain::Field::new(subdivisions);
 
         //let fbm = noise::Fbm::<noise::Fbm<noise::Perlin>>::default();
-        let noise_fn = noise::HybridMulti::<noise::Perlin>::default();
+        let noise_fn = noise::HybridMulti::<noise::Perlin>::new(seed);
 
         let noise_map: NoiseMap = noise::utils::PlaneMapBuilder::new(noise_fn)
             .set_size(height_map.size.x, height_map.size.y)
-            .set_x_bounds(0.0, domain::SIZE.x as f64 / 32.0) // bounds just determine the frequency
-            .set_y_bounds(0.0, domain::SIZE.y as f64 / 32.0)
+            .set_x_bounds(0.0, domain::size().x as f64 / 32.0) // bounds just determine the frequency
+            .set_y_bounds(0.0, domain::size().y as f64 / 32.0)
             .build();
 
         for y in 0..height_map.size.y {
@@ -87,9 +136,13 @@ pub fn reset_terrain_color(mesh: &mut Mesh) {
     mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, color_attr);
 }
 
-// Set the color at each vertex to the one in field.
-// If no range is provided, min and max values of the field are used.
-pub fn set_terrain_color(mesh: &mut Mesh, field: &domain::Field<f32>, range: Option<(f32, f32)>) {
+// Set all vertices to the same color, e.g. white to show a texture unaltered.
+pub fn set_terrain_uniform_color(mesh: &mut Mesh, color: LinearRgba) {
+    set_terrain_color_with(mesh, |_| color);
+}
+
+// Set the color at each vertex with a function of the position in the domain.
+fn set_terrain_color_with(mesh: &mut Mesh, color_fn: impl Fn(Vec2) -> LinearRgba + Sync) {
     let mut color_attr = mesh.remove_attribute(Mesh::ATTRIBUTE_COLOR).unwrap();
     let VertexAttributeValues::Float32x4(ref mut col_attr_vec) = color_attr else {
         panic!("Unexpected vertex format, expected Float32x4");
@@ -100,28 +153,17 @@ pub fn set_terrain_color(mesh: &mut Mesh, field: &domain::Field<f32>, range: Opt
         panic!("Unexpected vertex format, expected Float32x3");
     };
 
-    let (min, max) = if let Some(min_max) = range {
-        min_max
-    } else {
-        field.compute_min_max()
-    };
-    let cmap = color_map::ColorMap::new(min, max, color_map::ColorScheme::Incandescent);
-
     let task_pool = ComputeTaskPool::get();
     // Creating significantly more tasks than the available threads leads to more consistent timings.
     // todo: investigate again when there is more simulation work
     let chunk_size = 2048;
+    let half_size = domain::half_size().as_vec2();
     col_attr_vec.par_chunk_map_mut(task_pool, chunk_size, |index, chunk| {
         let mut idx = index * chunk_size;
         for col in chunk {
             let pos = pos_attr_vec[idx];
-            let pos_domain = Vec2::new(
-                pos[0] + domain::HALF_SIZE.x as f32,
-                pos[2] + domain::HALF_SIZE.y as f32,
-            );
-            *col = cmap
-                .get_color(field.get_bilinear(pos_domain))
-                .to_f32_array();
+            let pos_domain = Vec2::new(pos[0], pos[2]) + half_size;
+            *col = color_fn(pos_domain).to_f32_array();
             idx += 1;
         }
     });
@@ -135,7 +177,7 @@ pub fn generate_terrain_mesh(height_map: &domain::Field<f32>) -> Mesh {
     let mut vertex_colors: Vec<[f32; 4]> = Vec::with_capacity(num_vertices);
     let mut mesh: Mesh = Plane3d::default()
         .mesh()
-        .size(domain::SIZE.x as f32, domain::SIZE.y as f32)
+        .size(domain::size_f32().x, domain::size_f32().y)
         .subdivisions((height_map.size.x - 1) as u32)
         .into();
     // get positions
@@ -145,11 +187,9 @@ pub fn generate_terrain_mesh(height_map: &domain::Field<f32>) -> Mesh {
     };
 
     // modify y with height sampling
+    let half_size = domain::half_size().as_vec2();
     for pos in pos_attr_vec.iter_mut() {
-        let pos_domain = Vec2::new(
-            pos[0] + domain::HALF_SIZE.x as f32,
-            pos[2] + domain::HALF_SIZE.y as f32,
-        );
+        let pos_domain = Vec2::new(pos[0], pos[2]) + half_size;
         let h = height_map.get_bilinear(pos_domain);
         pos[1] = h;
 
@@ -164,34 +204,101 @@ pub fn generate_terrain_mesh(height_map: &domain::Field<f32>) -> Mesh {
     mesh
 }
 
-// Sets the image to represent the field. The Image is resized if the sizes don't match.
-// If no range is provided, min and max values of the field are used.
-#[allow(dead_code)]
+// Move the vertices of the terrain mesh which are affected by changes of the height map.
+pub fn update_terrain_mesh_system(
+    terrain_query: Query<(&Terrain, &Mesh3d)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let (terrain, mesh3d) = terrain_query.single().unwrap();
+    let height_map = &terrain.height_map;
+    let Some(region) = height_map.dirty_region() else {
+        return;
+    };
+    let Some(mesh) = meshes.get_mut(&mesh3d.0) else {
+        return;
+    };
+
+    // changed area in the domain, extended by one cell because of the bilinear interpolation
+    let min = (region.min.as_vec2() - Vec2::ONE) / height_map.idx_scale;
+    let max = region.max.as_vec2() / height_map.idx_scale;
+    let pos_attr = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION).unwrap();
+    let VertexAttributeValues::Float32x3(pos_attr_vec) = pos_attr else {
+        panic!("Unexpected vertex format, expected Float32x3");
+    };
+    let half_size = domain::half_size().as_vec2();
+    for pos in pos_attr_vec.iter_mut() {
+        let pos_domain = Vec2::new(pos[0], pos[2]) + half_size;
+        if pos_domain.cmpge(min).all() && pos_domain.cmple(max).all() {
+            pos[1] = height_map.get_bilinear(pos_domain);
+        }
+    }
+}
+
+// Sets the image to represent the field mapped by cmap.
+// The Image is resized if the sizes don't match.
+// If a region is provided, only the pixels inside of it are updated.
 pub fn set_image_from_field(
     image: &mut Image,
     field: &domain::Field<f32>,
-    range: Option<(f32, f32)>,
+    cmap: &color_map::ColorMap,
+    region: Option<domain::Region>,
+) {
+    set_image_with(image, field.size, region, |idx| cmap.get_color(field[idx]));
+}
+
+// Sets the image to show the color of the category of each cell.
+pub fn set_image_from_categories(
+    image: &mut Image,
+    field: &domain::Field<u8>,
+    category_color: impl Fn(u8) -> LinearRgba + Sync,
+    region: Option<domain::Region>,
 ) {
-    let field_size = UVec2::new(field.size.x as u32, field.size.y as u32);
+    set_image_with(image, field.size, region, |idx| category_color(field[idx]));
+}
+
+// Sets each pixel of the image to the color of its flat index.
+fn set_image_with(
+    image: &mut Image,
+    size: USizeVec2,
+    mut region: Option<domain::Region>,
+    color_fn: impl Fn(usize) -> LinearRgba + Sync,
+) {
+    let field_size = UVec2::new(size.x as u32, size.y as u32);
     if image.size() != field_size {
         image.resize(Extent3d {
             width: field_size.x,
             height: field_size.y,
             depth_or_array_layers: 1,
         });
+        region = None;
     }
 
-    let (min, max) = if let Some(min_max) = range {
-        min_max
-    } else {
-        field.compute_min_max()
-    };
-    let cmap = color_map::ColorMap::new(min, max, color_map::ColorScheme::Incandescent);
-
     const BYTES_PER_PIXEL: usize = 4;
 
-    if let Some(data) = &mut image.data {
-        let task_pool = ComputeTaskPool::get();
+    let Some(data) = &mut image.data else {
+        return;
+    };
+    let task_pool = ComputeTaskPool::get();
+    if let Some(region) = region {
+        // one task per row
+        let row_size = size.x * BYTES_PER_PIXEL;
+        data.par_chunk_map_mut(task_pool, row_size, |y, row| {
+            if y < region.min.y || y >= region.max.y {
+                return;
+            }
+            let (pixels, []) = row.as_chunks_mut::<BYTES_PER_PIXEL>() else {
+                unreachable!()
+            };
+            for (x, pixel) in pixels
+                .iter_mut()
+                .enumerate()
+                .take(region.max.x)
+                .skip(region.min.x)
+            {
+                *pixel = color_fn(x + y * size.x).to_u8_array();
+            }
+        });
+    } else {
         // Creating significantly more tasks than the available threads leads to more consistent timings.
         // todo: investigate again when there is more simulation work
         //let chunk_size = field.num_elem() / task_pool.thread_num() * BYTES_PER_PIXEL;
@@ -202,7 +309,7 @@ pub fn set_image_from_field(
                 unreachable!()
             };
             for bytes in sub_chunks {
-                let color = cmap.get_color(field[idx]).to_u8_array();
+                let color = color_fn(idx).to_u8_array();
                 *bytes = color;
                 idx += 1;
             }
@@ -244,6 +351,7 @@ pub fn setup_terrain(
     mut images: ResMut<Assets<Image>>,
     mut terrain_assets: ResMut<TerrainAssets>,
     asset_server: Res<AssetServer>,
+    options: Res<cli::Options>,
 ) {
     let repeated = |settings: &mut ImageLoaderSettings| {
         settings.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
@@ -285,13 +393,18 @@ pub fn setup_terrain(
         perceptual_roughness: 0.7294,
         reflectance: 0.1, // in the blender material specular ior is set to 0.5 but his may be a different property
         // texture is designed for 2m x 2m but the checkerboard is very visible so we do 4x4 instead
-        uv_transform: bevy::math::Affine2::from_scale(domain::SIZE_F32 * 0.25),
+        uv_transform: bevy::math::Affine2::from_scale(domain::size_f32() * 0.25),
         cull_mode: Some(Face::Back),
         ..default()
     };
     terrain_assets.ground_material = materials.add(terrain_material);
 
-    let terrain = Terrain::new(3);
+    // without a seed the terrain is always the same
+    let seed = match options.seed {
+        Some(seed) => seed as u32,
+        None => noise::HybridMulti::<noise::Perlin>::DEFAULT_SEED,
+    };
+    let terrain = Terrain::new(SUBDIVISIONS, seed);
 
     // (debug) visualize fields
     let mut field_vis_image = Image::new_fill(
@@ -319,10 +432,8 @@ pub fn setup_terrain(
     commands.spawn((
         Mesh3d(meshes.add(generate_terrain_mesh(&terrain.height_map))),
         MeshMaterial3d(terrain_assets.ground_material.clone()),
-        Transform::from_xyz(domain::HALF_SIZE.x as f32, 0.0, domain::HALF_SIZE.y as f32),
+        Transform::from_xyz(domain::half_size().x as f32, 0.0, domain::half_size().y as f32),
         terrain,
-        Surface {
-            veg_density: domain::Field::new(3),
-        },
+        Surface::new(SUBDIVISIONS),
     ));
 }
@@ -0,0 +1,260 @@
+//! Pause, single steps and the speed of the simulation.
+//! Space pauses or resumes the simulation, Period advances a single fixed step while paused and
+//! the arrow keys change the speed. The speed is limited by the measured cost of a fixed step so
+//! that the simulation keeps up with real time.
+
+use std::time::Instant;
+
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::input::egui_wants_any_keyboard_input;
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+
+const MIN_SPEED: f64 = 1.0 / 16.0;
+const MAX_SPEED: f64 = 256.0;
+// fraction of a frame which may be spent on fixed steps, the rest is left for rendering
+const SIMULATION_BUDGET: f64 = 0.5;
+// time over which the achieved speed is measured [s]
+const MEASURE_INTERVAL: f64 = 1.0;
+// the simulation is behind if it achieves less than this fraction of the requested speed
+const BEHIND_TOLERANCE: f64 = 0.9;
+
+#[derive(Resource)]
+pub struct TimeControl {
+    // fixed steps to run with the next frame while paused
+    requested_steps: u32,
+    // smoothed wall clock time of a fixed step [s]
+    step_cost: f64,
+    step_start: Option<Instant>,
+    // real and virtual time since the last measurement [s]
+    real_elapsed: f64,
+    virtual_elapsed: f64,
+    achieved_speed: f64,
+    is_behind: bool,
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        TimeControl {
+            requested_steps: 0,
+            step_cost: 0.0,
+            step_start: None,
+            real_elapsed: 0.0,
+            virtual_elapsed: 0.0,
+            achieved_speed: 1.0,
+            is_behind: false,
+        }
+    }
+}
+
+impl TimeControl {
+    pub fn request_step(&mut self) {
+        self.request_steps(1);
+    }
+
+    pub fn request_steps(&mut self, steps: u32) {
+        self.requested_steps = self.requested_steps.saturating_add(steps);
+    }
+
+    // Requested steps which did not run yet, including the ones handed to the next frame.
+    pub fn pending_steps(&self, fixed_time: &Time<Fixed>) -> u32 {
+        self.requested_steps + fixed_time.overstep_fraction_f64() as u32
+    }
+
+    // Highest speed at which the fixed steps of a frame still fit into the simulation budget.
+    pub fn max_speed(&self, fixed_time: &Time<Fixed>) -> f64 {
+        if self.step_cost <= 0.0 {
+            return MAX_SPEED;
+        }
+        // independent of the frame time: both the number of steps which fit into a frame and
+        // the number of steps required per frame at speed 1 are proportional to it
+        let steps_per_second = SIMULATION_BUDGET / self.step_cost;
+        let required_steps_per_second = 1.0 / fixed_time.timestep().as_secs_f64();
+        (steps_per_second / required_steps_per_second).clamp(1.0, MAX_SPEED)
+    }
+
+    // Ratio of simulated time to real time over the last measurement interval.
+    pub fn achieved_speed(&self) -> f64 {
+        self.achieved_speed
+    }
+
+    pub fn is_behind(&self) -> bool {
+        self.is_behind
+    }
+
+    pub fn set_speed(&self, time: &mut Time<Virtual>, fixed_time: &Time<Fixed>, speed: f64) {
+        time.set_relative_speed_f64(speed.clamp(MIN_SPEED, self.max_speed(fixed_time)));
+    }
+}
+
+pub struct TimeControlPlugin;
+
+impl Plugin for TimeControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeControl>()
+            .add_systems(FixedFirst, begin_step_system)
+            .add_systems(FixedLast, end_step_system)
+            .add_systems(
+                Update,
+                (
+                    time_control_input_system.run_if(not(egui_wants_any_keyboard_input)),
+                    single_step_system,
+                    measure_speed_system,
+                )
+                    .chain(),
+            )
+            .add_systems(EguiPrimaryContextPass, time_toolbar_system);
+    }
+}
+
+fn begin_step_system(mut control: ResMut<TimeControl>) {
+    control.step_start = Some(Instant::now());
+}
+
+fn end_step_system(mut control: ResMut<TimeControl>) {
+    let Some(start) = control.step_start.take() else {
+        return;
+    };
+    let cost = start.elapsed().as_secs_f64();
+    control.step_cost = if control.step_cost > 0.0 {
+        0.95 * control.step_cost + 0.05 * cost
+    } else {
+        cost
+    };
+}
+
+pub fn time_control_input_system(
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut time: ResMut<Time<Virtual>>,
+    fixed_time: Res<Time<Fixed>>,
+    mut control: ResMut<TimeControl>,
+) {
+    if key_input.just_pressed(KeyCode::Space) {
+        if time.is_paused() {
+            time.unpause();
+        } else {
+            time.pause();
+        }
+    }
+    if key_input.just_pressed(KeyCode::Period) && time.is_paused() {
+        control.request_step();
+    }
+
+    let relative_speed = time.relative_speed_f64();
+    if key_input.just_pressed(KeyCode::ArrowUp) {
+        control.set_speed(&mut time, &fixed_time, relative_speed * 2.0);
+    } else if key_input.just_pressed(KeyCode::ArrowDown) {
+        control.set_speed(&mut time, &fixed_time, relative_speed * 0.5);
+    }
+}
+
+// While paused, virtual time does not advance and no fixed steps are run. Requested steps are
+// added to the accumulated time of the fixed clock instead, which runs exactly these steps in the
+// next frame.
+pub fn single_step_system(
+    real_time: Res<Time<Real>>,
+    time: Res<Time<Virtual>>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+    mut control: ResMut<TimeControl>,
+) {
+    if !time.is_paused() {
+        control.requested_steps = 0;
+        return;
+    }
+    if control.requested_steps == 0 {
+        return;
+    }
+    // many requested steps are spread over several frames, each running as many as fit into
+    // the simulation budget, so that the app stays responsive
+    let batch = if control.step_cost > 0.0 {
+        (SIMULATION_BUDGET * real_time.delta_secs_f64() / control.step_cost) as u32
+    } else {
+        1
+    };
+    let steps = control.requested_steps.min(batch.max(1));
+    control.requested_steps -= steps;
+    let timestep = fixed_time.timestep();
+    fixed_time.accumulate_overstep(timestep * steps);
+}
+
+pub fn measure_speed_system(
+    real_time: Res<Time<Real>>,
+    mut time: ResMut<Time<Virtual>>,
+    fixed_time: Res<Time<Fixed>>,
+    mut control: ResMut<TimeControl>,
+) {
+    if time.is_paused() {
+        control.real_elapsed = 0.0;
+        control.virtual_elapsed = 0.0;
+        control.is_behind = false;
+        return;
+    }
+
+    control.real_elapsed += real_time.delta_secs_f64();
+    control.virtual_elapsed += time.delta_secs_f64();
+    if control.real_elapsed < MEASURE_INTERVAL {
+        return;
+    }
+
+    // Virtual time is only clamped if a frame takes longer than its maximum delta. Before that
+    // happens, the fixed steps already take up most of each frame.
+    let steps = control.virtual_elapsed / fixed_time.timestep().as_secs_f64();
+    let step_time = control.step_cost * steps;
+    control.achieved_speed = control.virtual_elapsed / control.real_elapsed;
+    control.is_behind = control.achieved_speed < BEHIND_TOLERANCE * time.relative_speed_f64()
+        || step_time > control.real_elapsed;
+    control.real_elapsed = 0.0;
+    control.virtual_elapsed = 0.0;
+
+    // the steps became more expensive since the speed was set
+    if time.relative_speed_f64() > control.max_speed(&fixed_time) {
+        let speed = time.relative_speed_f64();
+        control.set_speed(&mut time, &fixed_time, speed);
+    }
+}
+
+pub fn time_toolbar_system(
+    mut contexts: EguiContexts,
+    mut time: ResMut<Time<Virtual>>,
+    fixed_time: Res<Time<Fixed>>,
+    mut control: ResMut<TimeControl>,
+) -> Result {
+    egui::Window::new("Time")
+        .title_bar(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_TOP, egui::vec2(0.0, 5.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            ui.horizontal(|ui| {
+                let is_paused = time.is_paused();
+                if ui
+                    .button(if is_paused { "resume" } else { "pause" })
+                    .clicked()
+                {
+                    if is_paused {
+                        time.unpause();
+                    } else {
+                        time.pause();
+                    }
+                }
+                if ui
+                    .add_enabled(is_paused, egui::Button::new("step"))
+                    .clicked()
+                {
+                    control.request_step();
+                }
+
+                ui.separator();
+                let speed = time.relative_speed_f64();
+                if ui.button("½×").clicked() {
+                    control.set_speed(&mut time, &fixed_time, speed * 0.5);
+                }
+                ui.label(format!("{speed}×"));
+                if ui.button("2×").clicked() {
+                    control.set_speed(&mut time, &fixed_time, speed * 2.0);
+                }
+                ui.label(format!("(max {:.0}×)", control.max_speed(&fixed_time)));
+            });
+        });
+
+    Ok(())
+}
@@ -0,0 +1,391 @@
+//! Undo and redo of user edits with Ctrl+Z and Ctrl+Y (or Ctrl+Shift+Z).
+//! Only changes made by the user are recorded. Undoing an edit of a field restores the values
+//! from before the edit in the modified region, including changes the simulation made since.
+//! Undoing a parameter edit only restores the edited parameters.
+
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::*;
+use bevy_egui::input::egui_wants_any_keyboard_input;
+
+use crate::domain::{Field, Region};
+use crate::organism::{self, Organism, OrganismId};
+use crate::parameters::GeneralParameters;
+use crate::replay::Intervention;
+use crate::terrain::{Surface, Terrain};
+
+// limits of the undo history, the oldest entries are dropped first
+const MAX_ENTRIES: usize = 100;
+const MAX_MEMORY: usize = 64 * 1024 * 1024; // [bytes]
+
+// Fields which can be edited by the user.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum EditableField {
+    Height,
+    Moisture,
+    Nutrients,
+}
+
+impl EditableField {
+    pub const ALL: [EditableField; 3] = [
+        EditableField::Height,
+        EditableField::Moisture,
+        EditableField::Nutrients,
+    ];
+
+    // same as the name of the visualized field
+    pub fn name(self) -> &'static str {
+        match self {
+            EditableField::Height => "height",
+            EditableField::Moisture => "moisture",
+            EditableField::Nutrients => "nutrients",
+        }
+    }
+
+    pub fn get<'a>(self, terrain: &'a Terrain, surface: &'a Surface) -> &'a Field<f32> {
+        match self {
+            EditableField::Height => &terrain.height_map,
+            EditableField::Moisture => &surface.moisture,
+            EditableField::Nutrients => &surface.nutrients,
+        }
+    }
+
+    pub fn get_mut<'a>(
+        self,
+        terrain: &'a mut Terrain,
+        surface: &'a mut Surface,
+    ) -> &'a mut Field<f32> {
+        match self {
+            EditableField::Height => &mut terrain.height_map,
+            EditableField::Moisture => &mut surface.moisture,
+            EditableField::Nutrients => &mut surface.nutrients,
+        }
+    }
+}
+
+// An operation which reverts an edit. Applying it returns the operation to revert it again.
+// Organisms are referred to by their ids, which are kept when they are restored.
+pub enum Action {
+    RemoveOrganisms(Vec<OrganismId>),
+    SpawnOrganisms(Vec<(Transform, Organism)>),
+    SetField {
+        field: EditableField,
+        region: Region,
+        values: Vec<f32>,
+    },
+    // paths of parameters with their values
+    SetParameters(Vec<(String, serde_json::Value)>),
+}
+
+impl Action {
+    // Approximate memory usage [bytes].
+    fn size(&self) -> usize {
+        std::mem::size_of::<Action>()
+            + match self {
+                Action::RemoveOrganisms(ids) => ids.len() * std::mem::size_of::<OrganismId>(),
+                Action::SpawnOrganisms(organisms) => {
+                    organisms.len() * std::mem::size_of::<(Transform, Organism)>()
+                }
+                Action::SetField { values, .. } => values.len() * std::mem::size_of::<f32>(),
+                Action::SetParameters(values) => values
+                    .iter()
+                    .map(|(name, value)| name.len() + value.to_string().len())
+                    .sum(),
+            }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct UndoHistory {
+    undo: VecDeque<Action>,
+    redo: Vec<Action>,
+    // of the undo and redo entries
+    memory: usize,
+}
+
+impl UndoHistory {
+    // Record the action which reverts a new user edit.
+    pub fn record(&mut self, action: Action) {
+        for dropped in self.redo.drain(..) {
+            self.memory -= dropped.size();
+        }
+        self.push_undo(action);
+    }
+
+    // Organisms the user created are removed on undo.
+    pub fn record_spawned(&mut self, ids: Vec<OrganismId>) {
+        if !ids.is_empty() {
+            self.record(Action::RemoveOrganisms(ids));
+        }
+    }
+
+    // Organisms the user removed are recreated on undo.
+    pub fn record_removed(&mut self, organisms: Vec<(Transform, Organism)>) {
+        if !organisms.is_empty() {
+            self.record(Action::SpawnOrganisms(organisms));
+        }
+    }
+
+    fn push_undo(&mut self, action: Action) {
+        self.memory += action.size();
+        self.undo.push_back(action);
+        self.trim();
+    }
+
+    fn push_redo(&mut self, action: Action) {
+        self.memory += action.size();
+        self.redo.push(action);
+        self.trim();
+    }
+
+    fn pop_undo(&mut self) -> Option<Action> {
+        let action = self.undo.pop_back()?;
+        self.memory -= action.size();
+        Some(action)
+    }
+
+    fn pop_redo(&mut self) -> Option<Action> {
+        let action = self.redo.pop()?;
+        self.memory -= action.size();
+        Some(action)
+    }
+
+    // Drop the oldest undo entries and then the last redo entries until the history is within
+    // its limits. The next undo and redo entries are kept.
+    fn trim(&mut self) {
+        while self.undo.len() > MAX_ENTRIES || (self.memory > MAX_MEMORY && self.undo.len() > 1) {
+            let dropped = self.undo.pop_front().unwrap();
+            self.memory -= dropped.size();
+        }
+        while self.memory > MAX_MEMORY && self.redo.len() > 1 {
+            let dropped = self.redo.remove(0);
+            self.memory -= dropped.size();
+        }
+    }
+}
+
+// Values of a field before a user edit, e.g. a brush stroke.
+pub struct FieldSnapshot {
+    pub field: EditableField,
+    before: Field<f32>,
+    // union of the regions modified since the snapshot was taken
+    region: Option<Region>,
+}
+
+impl FieldSnapshot {
+    pub fn new(field: EditableField, terrain: &Terrain, surface: &Surface) -> Self {
+        FieldSnapshot {
+            field,
+            before: field.get(terrain, surface).clone(),
+            region: None,
+        }
+    }
+
+    pub fn add_region(&mut self, region: Region) {
+        self.region = Some(self.region.map_or(region, |r| r.union(region)));
+    }
+
+    // Action which restores the modified region.
+    pub fn into_action(self) -> Option<Action> {
+        let region = self.region?;
+        Some(Action::SetField {
+            field: self.field,
+            region,
+            values: self.before.read_region(region),
+        })
+    }
+}
+
+pub struct UndoPlugin;
+
+impl Plugin for UndoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UndoHistory>().add_systems(
+            Update,
+            undo_system.run_if(not(egui_wants_any_keyboard_input)),
+        );
+    }
+}
+
+pub fn undo_system(
+    mut commands: Commands,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<UndoHistory>,
+    mut field_query: Query<(&mut Terrain, &mut Surface)>,
+    mut organism_query: Query<(Entity, &mut Transform, &Organism)>,
+    mut general_params: ResMut<GeneralParameters>,
+    grass_assets: Res<crate::GrassAssets>,
+    mut organism_ids: ResMut<organism::OrganismIds>,
+) {
+    if !key_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let is_shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let is_undo = key_input.just_pressed(KeyCode::KeyZ) && !is_shift;
+    let is_redo = key_input.just_pressed(KeyCode::KeyY)
+        || (key_input.just_pressed(KeyCode::KeyZ) && is_shift);
+
+    let action = if is_undo {
+        history.pop_undo()
+    } else if is_redo {
+        history.pop_redo()
+    } else {
+        None
+    };
+    let Some(action) = action else {
+        return;
+    };
+    commands.write_message(if is_undo {
+        Intervention::Undo
+    } else {
+        Intervention::Redo
+    });
+
+    let (mut terrain, mut surface) = field_query.single_mut().unwrap();
+    let inverse = match action {
+        Action::RemoveOrganisms(ids) => {
+            let ids: HashSet<OrganismId> = ids.into_iter().collect();
+            let mut removed = Vec::new();
+            // organisms which died in the meantime are not found
+            for (id, transform, organism) in organism_query.iter() {
+                if ids.contains(&organism.id()) {
+                    organism::remove_organism(
+                        &mut commands,
+                        &mut surface,
+                        id,
+                        transform,
+                        organism,
+                        organism::DeathCause::Removed,
+                    );
+                    removed.push((*transform, organism.clone()));
+                }
+            }
+            Action::SpawnOrganisms(removed)
+        }
+        Action::SpawnOrganisms(organisms) => Action::RemoveOrganisms(
+            organisms
+                .iter()
+                .map(|(transform, organism)| {
+                    organism::spawn_copy(
+                        &mut commands,
+                        &mut organism_ids,
+                        &mut surface,
+                        &grass_assets,
+                        *transform,
+                        organism,
+                    );
+                    organism.id()
+                })
+                .collect(),
+        ),
+        Action::SetField {
+            field,
+            region,
+            values,
+        } => {
+            let target = field.get_mut(&mut terrain, &mut surface);
+            let current = target.read_region(region);
+            target.write_region(region, &values);
+
+            // keep organisms on the surface
+            if field == EditableField::Height {
+                let scale = terrain.height_map.idx_scale;
+                let area =
+                    Rect::from_corners(region.min.as_vec2() / scale, region.max.as_vec2() / scale);
+                for (_, mut transform, _) in organism_query.iter_mut() {
+                    let p = transform.translation.xz();
+                    if area.contains(p) {
+                        let depth = general_params.grass.below_surface_depth;
+                        transform.translation.y = terrain.height_map.get_bilinear(p) - depth;
+                    }
+                }
+            }
+            Action::SetField {
+                field,
+                region,
+                values: current,
+            }
+        }
+        Action::SetParameters(values) => Action::SetParameters(
+            values
+                .into_iter()
+                .filter_map(|(name, value)| {
+                    let current = general_params.get_value(&name).ok()?;
+                    general_params.set_value(&name, value).ok()?;
+                    Some((name, current))
+                })
+                .collect(),
+        ),
+    };
+
+    if is_undo {
+        history.push_redo(inverse);
+    } else {
+        history.push_undo(inverse);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::USizeVec2;
+
+    // field edit of a square region with the given side length
+    fn set_field(side: usize) -> Action {
+        Action::SetField {
+            field: EditableField::Moisture,
+            region: Region {
+                min: USizeVec2::ZERO,
+                max: USizeVec2::splat(side),
+            },
+            values: vec![0.0; side * side],
+        }
+    }
+
+    fn memory(history: &UndoHistory) -> usize {
+        history
+            .undo
+            .iter()
+            .chain(&history.redo)
+            .map(Action::size)
+            .sum()
+    }
+
+    #[test]
+    fn redo_entries_are_counted() {
+        let mut history = UndoHistory::default();
+        history.record(set_field(512));
+        history.record(set_field(16));
+        let action = history.pop_undo().unwrap();
+        history.push_redo(action);
+        assert_eq!(history.memory, memory(&history));
+        assert_eq!(history.redo.len(), 1);
+
+        // a new edit drops the redo entries
+        history.record(set_field(8));
+        assert!(history.redo.is_empty());
+        assert_eq!(history.memory, memory(&history));
+    }
+
+    #[test]
+    fn the_history_is_bounded() {
+        let mut history = UndoHistory::default();
+        for _ in 0..2 * MAX_ENTRIES {
+            history.record(set_field(4));
+        }
+        assert_eq!(history.undo.len(), MAX_ENTRIES);
+
+        // entries which are pushed again, e.g. on redo, are also trimmed
+        let side = 2048;
+        for _ in 0..MAX_MEMORY / (side * side * std::mem::size_of::<f32>()) + 2 {
+            history.push_undo(set_field(side));
+        }
+        assert!(history.memory <= MAX_MEMORY);
+        assert_eq!(history.memory, memory(&history));
+        while let Some(action) = history.pop_undo() {
+            history.push_redo(action);
+        }
+        assert!(history.memory <= MAX_MEMORY);
+        assert_eq!(history.memory, memory(&history));
+    }
+}
@@ -0,0 +1,245 @@
+use bevy::prelude::*;
+use bevy_prng::WyRand;
+use bevy_rand::prelude::*;
+use rand::prelude::*;
+use std::f32::consts::PI;
+
+use crate::calendar;
+use crate::parameters;
+use crate::terrain::Surface;
+
+#[derive(
+    Default,
+    PartialEq,
+    Copy,
+    Clone,
+    Debug,
+    egui_probe::EguiProbe,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub enum ClimatePreset {
+    #[default]
+    Temperate,
+    Mediterranean,
+    Arid,
+    Tropical,
+}
+
+// Parameters of the stochastic weather generator. Probabilities are per simulated day.
+#[derive(Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ClimateParameters {
+    // transition probabilities of the dry/rainy Markov chain
+    pub dry_to_rain_prob: f32,
+    pub rain_to_dry_prob: f32,
+    pub mean_precipitation: f32, // [mm/day] on a rainy day
+    pub storm_prob: f32,         // chance of a rainy day to be a storm
+    pub storm_intensity: f32,    // precipitation multiplier of storms
+    pub drought_prob: f32,       // chance of a drought to start on a dry day
+    pub drought_min_days: u32,
+    pub drought_max_days: u32,
+    pub mean_temperature: f32,   // [°C]
+    pub seasonal_amplitude: f32, // [°C]
+    pub daily_variation: f32,    // [°C]
+    pub rain_cooling: f32,       // [°C]
+    pub drought_warming: f32,    // [°C]
+}
+
+impl ClimateParameters {
+    pub fn from_preset(preset: ClimatePreset) -> Self {
+        match preset {
+            ClimatePreset::Temperate => ClimateParameters::default(),
+            ClimatePreset::Mediterranean => ClimateParameters {
+                dry_to_rain_prob: 0.2,
+                rain_to_dry_prob: 0.6,
+                mean_precipitation: 8.0,
+                storm_prob: 0.15,
+                drought_prob: 0.03,
+                drought_max_days: 35,
+                mean_temperature: 17.0,
+                seasonal_amplitude: 8.0,
+                ..default()
+            },
+            ClimatePreset::Arid => ClimateParameters {
+                dry_to_rain_prob: 0.05,
+                rain_to_dry_prob: 0.8,
+                mean_precipitation: 6.0,
+                storm_prob: 0.3,
+                storm_intensity: 5.0,
+                drought_prob: 0.05,
+                drought_min_days: 21,
+                drought_max_days: 60,
+                mean_temperature: 24.0,
+                seasonal_amplitude: 10.0,
+                daily_variation: 5.0,
+                ..default()
+            },
+            ClimatePreset::Tropical => ClimateParameters {
+                dry_to_rain_prob: 0.6,
+                rain_to_dry_prob: 0.3,
+                mean_precipitation: 15.0,
+                storm_prob: 0.2,
+                drought_prob: 0.005,
+                mean_temperature: 26.0,
+                seasonal_amplitude: 2.0,
+                daily_variation: 1.5,
+                rain_cooling: 1.0,
+                ..default()
+            },
+        }
+    }
+}
+
+impl Default for ClimateParameters {
+    fn default() -> Self {
+        ClimateParameters {
+            dry_to_rain_prob: 0.35,
+            rain_to_dry_prob: 0.5,
+            mean_precipitation: 6.0,
+            storm_prob: 0.05,
+            storm_intensity: 4.0,
+            drought_prob: 0.01,
+            drought_min_days: 14,
+            drought_max_days: 28,
+            mean_temperature: 10.0,
+            seasonal_amplitude: 9.0,
+            daily_variation: 3.0,
+            rain_cooling: 2.0,
+            drought_warming: 3.0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, egui_probe::EguiProbe, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WeatherParameters {
+    // the climate parameters are reset when the preset changes
+    pub preset: ClimatePreset,
+    pub climate: ClimateParameters,
+    pub soil_capacity: f32, // [mm] water that can be stored in the soil
+    pub evaporation: f32,   // [1/day] relative water loss at 15°C
+    // preset the climate was last reset to, None for loaded parameters whose climate is kept
+    #[serde(skip)]
+    #[egui_probe(skip)]
+    applied_preset: Option<ClimatePreset>,
+}
+
+impl Default for WeatherParameters {
+    fn default() -> Self {
+        WeatherParameters {
+            preset: ClimatePreset::default(),
+            climate: ClimateParameters::default(),
+            soil_capacity: 100.0,
+            evaporation: 0.05,
+            applied_preset: None,
+        }
+    }
+}
+
+#[derive(Resource, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Weather {
+    pub is_raining: bool,
+    pub is_storm: bool,
+    pub precipitation: f32, // [mm/day]
+    pub temperature: f32,   // [°C]
+    drought_days_left: u32,
+    // day of the calendar the weather was rolled for
+    day: Option<u32>,
+}
+
+impl Weather {
+    pub fn is_drought(&self) -> bool {
+        self.drought_days_left > 0
+    }
+
+    // Start a drought immediately, e.g. from a scenario.
+    pub fn start_drought(&mut self, days: u32) {
+        self.drought_days_left = days;
+        self.is_raining = false;
+        self.is_storm = false;
+        self.precipitation = 0.0;
+    }
+
+    // Advance the weather to the next day.
+    fn roll_day(&mut self, climate: &ClimateParameters, year_fraction: f32, rng: &mut impl Rng) {
+        if self.drought_days_left > 0 {
+            self.drought_days_left -= 1;
+        } else if !self.is_raining && rng.random::<f32>() < climate.drought_prob {
+            let min_days = climate.drought_min_days;
+            self.drought_days_left =
+                rng.random_range(min_days..=climate.drought_max_days.max(min_days));
+        }
+
+        self.is_raining = if self.is_drought() {
+            false
+        } else if self.is_raining {
+            rng.random::<f32>() >= climate.rain_to_dry_prob
+        } else {
+            rng.random::<f32>() < climate.dry_to_rain_prob
+        };
+
+        self.is_storm = self.is_raining && rng.random::<f32>() < climate.storm_prob;
+        self.precipitation = if self.is_raining {
+            // exponentially distributed amount
+            let amount = -climate.mean_precipitation * (1.0 - rng.random::<f32>()).ln();
+            if self.is_storm {
+                amount * climate.storm_intensity
+            } else {
+                amount
+            }
+        } else {
+            0.0
+        };
+
+        // the year starts with spring, the warmest time is in the middle of summer
+        let seasonal = (2.0 * PI * (year_fraction - 0.125)).sin();
+        let mut temperature = climate.mean_temperature
+            + climate.seasonal_amplitude * seasonal
+            + climate.daily_variation * (2.0 * rng.random::<f32>() - 1.0);
+        if self.is_raining {
+            temperature -= climate.rain_cooling;
+        }
+        if self.is_drought() {
+            temperature += climate.drought_warming;
+        }
+        self.temperature = temperature;
+    }
+}
+
+// Relative evaporation rate at the given temperature.
+fn evaporation_factor(temperature: f32) -> f32 {
+    (1.0 + 0.05 * (temperature - 15.0)).max(0.0)
+}
+
+pub fn weather_system(
+    mut weather: ResMut<Weather>,
+    mut surface_query: Query<&mut Surface>,
+    mut general_params: ResMut<parameters::GeneralParameters>,
+    calendar: Res<calendar::Calendar>,
+    time: Res<Time>,
+    mut rng: Single<&mut WyRand, With<GlobalRng>>,
+) {
+    if general_params.weather.applied_preset != Some(general_params.weather.preset) {
+        let params = &mut general_params.weather;
+        if params.applied_preset.is_some() {
+            params.climate = ClimateParameters::from_preset(params.preset);
+        }
+        params.applied_preset = Some(params.preset);
+    }
+
+    let params = &general_params.weather;
+    if weather.day != Some(calendar.day()) {
+        weather.day = Some(calendar.day());
+        weather.roll_day(&params.climate, calendar.year_fraction(), &mut *rng);
+    }
+
+    // soil water balance
+    let dt_days = time.delta_secs() / general_params.sun.day_duration;
+    let infiltration = weather.precipitation / params.soil_capacity * dt_days;
+    let evaporation = params.evaporation * evaporation_factor(weather.temperature) * dt_days;
+    let mut surface = surface_query.single_mut().unwrap();
+    for m in surface.moisture.iter_mut() {
+        *m = (*m + infiltration - evaporation * *m).clamp(0.0, 1.0);
+    }
+}
@@ -0,0 +1,213 @@
+//! Drives a headless app over the remote control interface like an external client would.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use eco_sim::Options;
+use serde_json::{Value, json};
+
+const SEED: u64 = 7;
+const FIXED_HZ: f64 = 10.0;
+const TIMEOUT: Duration = Duration::from_secs(120);
+// JSON-RPC error code of invalid parameters
+const INVALID_PARAMS: i64 = -32602;
+
+// The app runs until the test process exits, so all tests share one server.
+fn server_port() -> u16 {
+    static PORT: OnceLock<u16> = OnceLock::new();
+    *PORT.get_or_init(|| {
+        // a free port is found by binding to port 0, it is released again for the server
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("no free port")
+            .port();
+        let output_dir = std::env::temp_dir().join(format!("eco-sim-remote-{port}"));
+        thread::spawn(move || {
+            let options = Options {
+                seed: Some(SEED),
+                fixed_hz: FIXED_HZ,
+                output_dir,
+                is_headless: true,
+                remote_port: Some(port),
+                ..Options::default()
+            };
+            eco_sim::build_app(options).unwrap().run();
+        });
+        port
+    })
+}
+
+fn post(port: u16, body: &str) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (header, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| std::io::Error::other("incomplete response"))?;
+    if !header
+        .to_ascii_lowercase()
+        .contains("transfer-encoding: chunked")
+    {
+        return Ok(body.to_string());
+    }
+
+    let mut decoded = String::new();
+    let mut rest = body;
+    while let Some((size, tail)) = rest.split_once("\r\n") {
+        let size = usize::from_str_radix(size.trim(), 16).map_err(std::io::Error::other)?;
+        if size == 0 {
+            break;
+        }
+        decoded.push_str(&tail[..size]);
+        rest = &tail[size + 2..];
+    }
+    Ok(decoded)
+}
+
+// Full JSON-RPC response of a call, retried until the server accepts connections.
+fn call(method: &str, params: Value) -> Value {
+    let port = server_port();
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    let start = Instant::now();
+    loop {
+        match post(port, &request.to_string()) {
+            Ok(body) => return serde_json::from_str(&body).expect("invalid JSON response"),
+            Err(_) if start.elapsed() < TIMEOUT => thread::sleep(Duration::from_millis(100)),
+            Err(err) => panic!("no response to {method}: {err}"),
+        }
+    }
+}
+
+fn result(method: &str, params: Value) -> Value {
+    let response = call(method, params);
+    assert!(
+        response.get("error").is_none(),
+        "{method} failed: {response}"
+    );
+    response["result"].clone()
+}
+
+fn error_code(method: &str, params: Value) -> i64 {
+    let response = call(method, params);
+    response["error"]["code"]
+        .as_i64()
+        .unwrap_or_else(|| panic!("{method} did not fail: {response}"))
+}
+
+fn population() -> Value {
+    result("eco.population", json!({}))
+}
+
+// Wait until all requested steps ran.
+fn wait_for_steps() -> Value {
+    let start = Instant::now();
+    loop {
+        let population = population();
+        if population["pending_steps"] == 0 {
+            return population;
+        }
+        assert!(start.elapsed() < TIMEOUT, "steps did not finish");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// The tests change the shared simulation, so they run one after another in a single test.
+#[test]
+fn remote_control() {
+    // the terrain is only generated after the first frames
+    let start = Instant::now();
+    while call("eco.field", json!({"name": "height"}))
+        .get("error")
+        .is_some()
+    {
+        assert!(start.elapsed() < TIMEOUT, "the terrain was not generated");
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    assert_eq!(result("eco.pause", json!({})), Value::Null);
+    let before = wait_for_steps();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(population()["time"], before["time"], "paused time advanced");
+
+    // stepping
+    assert_eq!(result("eco.step", json!({"steps": 5})), Value::Null);
+    let after = wait_for_steps();
+    let elapsed = after["time"].as_f64().unwrap() - before["time"].as_f64().unwrap();
+    assert!(
+        (elapsed - 5.0 / FIXED_HZ).abs() < 1e-6,
+        "stepped {elapsed} s"
+    );
+    assert_eq!(
+        error_code("eco.step", json!({"steps": "many"})),
+        INVALID_PARAMS
+    );
+    assert_eq!(
+        error_code("eco.step", json!({"steps": u32::MAX})),
+        INVALID_PARAMS
+    );
+
+    // fields
+    let field = result("eco.field", json!({"name": "moisture"}));
+    let size = field["size"].as_array().unwrap();
+    let values = field["values"].as_array().unwrap();
+    let num_values = size[0].as_u64().unwrap() * size[1].as_u64().unwrap();
+    assert_eq!(values.len() as u64, num_values);
+    assert!(
+        values
+            .iter()
+            .all(|v| (0.0..=1.0).contains(&v.as_f64().unwrap()))
+    );
+    assert_eq!(
+        error_code("eco.field", json!({"name": "unknown"})),
+        INVALID_PARAMS
+    );
+    assert_eq!(error_code("eco.field", json!({})), INVALID_PARAMS);
+
+    // spawning and killing
+    let count = population()["population"].as_u64().unwrap();
+    let ids = result(
+        "eco.spawn",
+        json!({"positions": [[10.0, 10.0], [20.0, 30.0]]}),
+    );
+    let ids = ids.as_array().unwrap().clone();
+    assert_eq!(ids.len(), 2);
+    // persistent organism ids, not entities
+    assert!(ids.iter().all(Value::is_u64));
+    assert_ne!(ids[0], ids[1]);
+    assert_eq!(population()["population"].as_u64().unwrap(), count + 2);
+
+    assert_eq!(
+        error_code(
+            "eco.spawn",
+            json!({"positions": [[10.0, 10.0], [-1.0, 5.0]]})
+        ),
+        INVALID_PARAMS
+    );
+    assert_eq!(
+        error_code("eco.spawn", json!({"positions": [[1.0e6, 5.0]]})),
+        INVALID_PARAMS
+    );
+    assert_eq!(population()["population"].as_u64().unwrap(), count + 2);
+
+    assert_eq!(result("eco.kill", json!({"ids": [ids[0]]})), Value::Null);
+    assert_eq!(population()["population"].as_u64().unwrap(), count + 1);
+    // already removed
+    assert_eq!(
+        error_code("eco.kill", json!({"ids": [ids[0], ids[1]]})),
+        INVALID_PARAMS
+    );
+    assert_eq!(population()["population"].as_u64().unwrap(), count + 1);
+    assert_eq!(
+        error_code("eco.kill", json!({"ids": "all"})),
+        INVALID_PARAMS
+    );
+}